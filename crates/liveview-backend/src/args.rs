@@ -12,4 +12,12 @@ pub(crate) struct Args {
     /// Path to the data directory
     #[arg(short, long, env = "DATA_PATH", visible_alias = "data")]
     pub(crate) data_path: PathBuf,
+
+    /// Directory of the persistent cache, which is only kept in memory when unset
+    #[arg(long, env = "STORE_PATH")]
+    pub(crate) store_path: Option<PathBuf>,
+
+    /// Maximum number of concurrent subscriptions per socket
+    #[arg(long, env = "MAX_SUBSCRIPTIONS", default_value_t = 16)]
+    pub(crate) max_subscriptions: usize,
 }
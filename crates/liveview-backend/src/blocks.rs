@@ -0,0 +1,450 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use alloy::{
+    eips::BlockNumberOrTag,
+    primitives::B256,
+    providers::{Provider, RootProvider},
+    rpc::types::BlockTransactionsKind,
+    transports::{BoxTransport, TransportError},
+};
+use serde::Deserialize;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, warn};
+
+/// Number of recent blocks whose hashes are remembered
+const TRACKED_BLOCKS: usize = 128;
+const REORG_CAPACITY: usize = 16;
+/// Number of block timestamps remembered
+const CACHED_TIMESTAMPS: usize = 4096;
+/// The safe and finalized heads move slowly, so they are only fetched every few polls
+const FINALITY_POLL_TICKS: u64 = 5;
+/// Deepest block a confirmation policy may wait for, unconfirmed events are held in memory
+pub(crate) const MAX_CONFIRMATIONS: u64 = 10_000;
+
+/// When a Transfer is considered confirmed
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Confirmation {
+    /// As soon as it is included in a block
+    #[default]
+    Latest,
+    /// Once its block is this many blocks deep, counting the block itself
+    Confirmations(u64),
+    /// Once its block is at or below the `safe` head
+    Safe,
+    /// Once its block is at or below the `finalized` head
+    Finalized,
+}
+
+impl Confirmation {
+    pub(crate) fn is_met(self, block_number: u64, heads: &Heads) -> bool {
+        match self {
+            Confirmation::Latest => true,
+            Confirmation::Confirmations(confirmations) => {
+                heads.latest + 1 >= block_number.saturating_add(confirmations)
+            }
+            Confirmation::Safe => heads.safe.is_some_and(|safe| safe >= block_number),
+            Confirmation::Finalized => heads
+                .finalized
+                .is_some_and(|finalized| finalized >= block_number),
+        }
+    }
+
+    /// Whether the policy confirms events in a bounded number of blocks
+    pub(crate) fn is_valid(self) -> bool {
+        match self {
+            Confirmation::Confirmations(confirmations) => confirmations <= MAX_CONFIRMATIONS,
+            Confirmation::Latest | Confirmation::Safe | Confirmation::Finalized => true,
+        }
+    }
+
+    /// Block tag of the head the policy waits for, if the chain has to report it
+    pub(crate) fn head_tag(self) -> Option<BlockNumberOrTag> {
+        match self {
+            Confirmation::Latest | Confirmation::Confirmations(_) => None,
+            Confirmation::Safe => Some(BlockNumberOrTag::Safe),
+            Confirmation::Finalized => Some(BlockNumberOrTag::Finalized),
+        }
+    }
+
+    /// Whether the chain reports the head the policy waits for
+    pub(crate) fn is_supported(self, heads: &Heads) -> bool {
+        match self {
+            Confirmation::Latest | Confirmation::Confirmations(_) => true,
+            Confirmation::Safe => heads.safe.is_some(),
+            Confirmation::Finalized => heads.finalized.is_some(),
+        }
+    }
+}
+
+/// Latest known block numbers of a chain
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Heads {
+    pub(crate) latest: u64,
+    /// `None` until known, or when the chain doesn't report it
+    pub(crate) safe: Option<u64>,
+    pub(crate) finalized: Option<u64>,
+}
+
+/// Blocks after `fork_block` were replaced by another branch
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Reorg {
+    /// Last block shared by the old and the new branch
+    pub(crate) fork_block: u64,
+    /// Number of tracked blocks which were replaced
+    pub(crate) depth: u64,
+}
+
+/// Tracks the hashes of the recent blocks of a chain to detect reorganizations,
+/// including on providers which never report removed logs
+#[derive(Debug)]
+pub(crate) struct BlockTracker {
+    chain: String,
+    hashes: Mutex<BTreeMap<u64, B256>>,
+    /// Timestamps of recently seen blocks, keyed by number and hash to survive reorgs
+    timestamps: Mutex<BTreeMap<(u64, B256), u64>>,
+    reorgs: broadcast::Sender<Reorg>,
+    heads: watch::Sender<Heads>,
+}
+
+impl BlockTracker {
+    pub(crate) fn new(chain: String) -> Self {
+        Self {
+            chain,
+            hashes: Mutex::new(BTreeMap::new()),
+            timestamps: Mutex::new(BTreeMap::new()),
+            reorgs: broadcast::channel(REORG_CAPACITY).0,
+            heads: watch::Sender::new(Heads::default()),
+        }
+    }
+
+    pub(crate) fn heads(&self) -> watch::Receiver<Heads> {
+        self.heads.subscribe()
+    }
+
+    pub(crate) fn subscribe_reorgs(&self) -> broadcast::Receiver<Reorg> {
+        self.reorgs.subscribe()
+    }
+
+    /// Poll the head of the chain on `interval` and compare it with the tracked blocks
+    pub(crate) fn spawn(
+        self: &Arc<Self>,
+        provider: Arc<RootProvider<BoxTransport>>,
+        interval: Duration,
+    ) {
+        let tracker = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            for tick in 0.. {
+                ticker.tick().await;
+
+                if let Err(err) = tracker.update(&provider).await {
+                    warn!(chain = tracker.chain, %err, "Failed to track blocks");
+                }
+                if tick % FINALITY_POLL_TICKS == 0 {
+                    tracker.update_finality(&provider).await;
+                }
+            }
+        });
+    }
+
+    /// Whether the block `number` with `hash` is on the canonical chain,
+    /// `None` when it cannot be determined
+    pub(crate) async fn is_canonical(
+        &self,
+        provider: &RootProvider<BoxTransport>,
+        number: u64,
+        hash: B256,
+    ) -> Option<bool> {
+        let tracked = self.hashes.lock().unwrap().get(&number).copied();
+        if let Some(tracked) = tracked {
+            return Some(tracked == hash);
+        }
+
+        let canonical = fetch_hash(provider, BlockNumberOrTag::Number(number))
+            .await
+            .ok()??;
+        Some(canonical.1 == hash)
+    }
+
+    /// Timestamp of the block `number` with `hash` in seconds, from the cache or its header
+    pub(crate) async fn timestamp(
+        &self,
+        provider: &RootProvider<BoxTransport>,
+        number: u64,
+        hash: B256,
+    ) -> Option<u64> {
+        let cached = self
+            .timestamps
+            .lock()
+            .unwrap()
+            .get(&(number, hash))
+            .copied();
+        if let Some(cached) = cached {
+            return Some(cached);
+        }
+
+        let block = provider
+            .get_block_by_hash(hash, BlockTransactionsKind::Hashes)
+            .await
+            .ok()??;
+        self.insert_timestamp(number, hash, block.header.timestamp);
+
+        Some(block.header.timestamp)
+    }
+
+    async fn update(&self, provider: &RootProvider<BoxTransport>) -> Result<(), TransportError> {
+        let Some(header) = fetch_header(provider, BlockNumberOrTag::Latest).await? else {
+            return Ok(());
+        };
+        let (number, hash, parent_hash) = (header.number, header.hash, header.parent_hash);
+        self.insert_timestamp(number, hash, header.timestamp);
+
+        let consistent = match self.newest() {
+            None => true,
+            Some(newest) => match extends(newest, number, hash, parent_hash) {
+                Some(consistent) => consistent,
+                // Blocks were skipped or the chain got shorter, check the newest tracked block directly
+                None => fetch_hash(provider, BlockNumberOrTag::Number(newest.0))
+                    .await?
+                    .is_some_and(|(_, canonical)| canonical == newest.1),
+            },
+        };
+
+        if !consistent {
+            self.find_fork(provider).await?;
+        }
+        self.advance(number, hash);
+
+        Ok(())
+    }
+
+    /// Track a new head, which is lower than the previous one when the chain got shorter
+    fn advance(&self, number: u64, hash: B256) {
+        self.insert(number, hash);
+        self.heads.send_modify(|heads| heads.latest = number);
+    }
+
+    async fn update_finality(&self, provider: &RootProvider<BoxTransport>) {
+        // Chains without these tags answer with an error, which leaves the head unknown
+        match fetch_hash(provider, BlockNumberOrTag::Safe).await {
+            Ok(safe) => self
+                .heads
+                .send_modify(|heads| heads.safe = safe.map(|(number, _)| number)),
+            Err(err) => debug!(chain = self.chain, %err, "Failed to fetch the safe block"),
+        }
+        match fetch_hash(provider, BlockNumberOrTag::Finalized).await {
+            Ok(finalized) => self
+                .heads
+                .send_modify(|heads| heads.finalized = finalized.map(|(number, _)| number)),
+            Err(err) => debug!(chain = self.chain, %err, "Failed to fetch the finalized block"),
+        }
+    }
+
+    /// Walk back the tracked blocks until one is still canonical and report the reorg
+    async fn find_fork(&self, provider: &RootProvider<BoxTransport>) -> Result<(), TransportError> {
+        let tracked = self
+            .hashes
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .map(|(number, hash)| (*number, *hash))
+            .collect::<Vec<_>>();
+        let Some(&(oldest, _)) = tracked.last() else {
+            return Ok(());
+        };
+
+        // Deeper than what is tracked, everything tracked is replaced
+        let mut fork_block = oldest.saturating_sub(1);
+        let mut depth = 0;
+        for (number, hash) in tracked {
+            let canonical = fetch_hash(provider, BlockNumberOrTag::Number(number)).await?;
+            if canonical.is_some_and(|(_, canonical)| canonical == hash) {
+                fork_block = number;
+                break;
+            }
+            depth += 1;
+        }
+
+        self.rewind(fork_block);
+
+        warn!(
+            chain = self.chain,
+            fork_block, depth, "Chain reorganization detected"
+        );
+        self.reorgs.send(Reorg { fork_block, depth }).ok();
+
+        Ok(())
+    }
+
+    /// Forget the tracked blocks after `fork_block`
+    fn rewind(&self, fork_block: u64) {
+        self.hashes
+            .lock()
+            .unwrap()
+            .retain(|number, _| *number <= fork_block);
+    }
+
+    fn newest(&self) -> Option<(u64, B256)> {
+        let hashes = self.hashes.lock().unwrap();
+        hashes
+            .last_key_value()
+            .map(|(number, hash)| (*number, *hash))
+    }
+
+    fn insert(&self, number: u64, hash: B256) {
+        let mut hashes = self.hashes.lock().unwrap();
+        if hashes.insert(number, hash).is_none() {
+            debug!(chain = self.chain, number, ?hash, "New block");
+        }
+        while hashes.len() > TRACKED_BLOCKS {
+            hashes.pop_first();
+        }
+    }
+
+    fn insert_timestamp(&self, number: u64, hash: B256, timestamp: u64) {
+        let mut timestamps = self.timestamps.lock().unwrap();
+        timestamps.insert((number, hash), timestamp);
+        while timestamps.len() > CACHED_TIMESTAMPS {
+            timestamps.pop_first();
+        }
+    }
+}
+
+/// Whether a head is consistent with the newest tracked block,
+/// `None` when only the chain can tell
+fn extends(newest: (u64, B256), number: u64, hash: B256, parent_hash: B256) -> Option<bool> {
+    let (newest_number, newest_hash) = newest;
+    if number == newest_number + 1 {
+        // The usual case of a single new block
+        Some(parent_hash == newest_hash)
+    } else if number == newest_number {
+        Some(hash == newest_hash)
+    } else {
+        None
+    }
+}
+
+struct Header {
+    number: u64,
+    hash: B256,
+    parent_hash: B256,
+    timestamp: u64,
+}
+
+async fn fetch_header(
+    provider: &RootProvider<BoxTransport>,
+    number: BlockNumberOrTag,
+) -> Result<Option<Header>, TransportError> {
+    let block = provider
+        .get_block_by_number(number, BlockTransactionsKind::Hashes)
+        .await?;
+
+    Ok(block.map(|block| Header {
+        number: block.header.number,
+        hash: block.header.hash,
+        parent_hash: block.header.parent_hash,
+        timestamp: block.header.timestamp,
+    }))
+}
+
+async fn fetch_hash(
+    provider: &RootProvider<BoxTransport>,
+    number: BlockNumberOrTag,
+) -> Result<Option<(u64, B256)>, TransportError> {
+    let header = fetch_header(provider, number).await?;
+    Ok(header.map(|header| (header.number, header.hash)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heads(latest: u64, safe: Option<u64>, finalized: Option<u64>) -> Heads {
+        Heads {
+            latest,
+            safe,
+            finalized,
+        }
+    }
+
+    #[test]
+    fn confirms_by_policy() {
+        let heads = heads(110, Some(100), Some(90));
+        for (confirmation, block_number, confirmed) in [
+            (Confirmation::Latest, 110, true),
+            (Confirmation::Latest, 111, true),
+            // The block itself counts as the first confirmation
+            (Confirmation::Confirmations(1), 110, true),
+            (Confirmation::Confirmations(1), 111, false),
+            (Confirmation::Confirmations(11), 100, true),
+            (Confirmation::Confirmations(12), 100, false),
+            (Confirmation::Confirmations(0), 111, true),
+            (Confirmation::Confirmations(u64::MAX), 1, false),
+            (Confirmation::Safe, 100, true),
+            (Confirmation::Safe, 101, false),
+            (Confirmation::Finalized, 90, true),
+            (Confirmation::Finalized, 91, false),
+        ] {
+            assert_eq!(
+                confirmation.is_met(block_number, &heads),
+                confirmed,
+                "{confirmation:?} of block {block_number}"
+            );
+        }
+    }
+
+    #[test]
+    fn waits_for_unreported_heads() {
+        let heads = heads(110, None, None);
+        assert!(!Confirmation::Safe.is_met(1, &heads));
+        assert!(!Confirmation::Finalized.is_met(1, &heads));
+        assert!(!Confirmation::Safe.is_supported(&heads));
+        assert!(!Confirmation::Finalized.is_supported(&heads));
+        assert!(Confirmation::Latest.is_supported(&heads));
+        assert!(Confirmation::Confirmations(12).is_supported(&heads));
+    }
+
+    #[test]
+    fn links_heads_to_the_newest_block() {
+        let newest = (100, B256::repeat_byte(1));
+        let other = B256::repeat_byte(2);
+
+        assert_eq!(extends(newest, 101, other, newest.1), Some(true));
+        assert_eq!(extends(newest, 101, other, other), Some(false));
+        assert_eq!(extends(newest, 100, newest.1, other), Some(true));
+        assert_eq!(extends(newest, 100, other, other), Some(false));
+        // Skipped blocks and shorter chains need the canonical hash of the newest block
+        assert_eq!(extends(newest, 105, other, other), None);
+        assert_eq!(extends(newest, 98, other, other), None);
+    }
+
+    #[test]
+    fn lowers_the_head_after_a_reorg() {
+        let tracker = BlockTracker::new("test".to_owned());
+        let heads = tracker.heads();
+        for number in 100..=105 {
+            tracker.advance(number, B256::repeat_byte(1));
+        }
+        assert_eq!(heads.borrow().latest, 105);
+
+        // The new branch forks after block 101 and is shorter
+        let replaced = B256::repeat_byte(2);
+        tracker.rewind(101);
+        assert_eq!(tracker.newest(), Some((101, B256::repeat_byte(1))));
+        tracker.advance(103, replaced);
+        assert_eq!(heads.borrow().latest, 103);
+        assert_eq!(tracker.newest(), Some((103, replaced)));
+        assert_eq!(
+            extends((103, replaced), 104, B256::ZERO, replaced),
+            Some(true)
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use alloy::primitives::{Address, U256};
+use serde::Serialize;
+use tokio::sync::OnceCell;
+
+use crate::{data, metadata::TokenInfo, tokens::TokenData};
+
+#[derive(Debug)]
+struct Entry<V> {
+    /// Empty while the value is being looked up, or after the lookup failed
+    value: OnceCell<V>,
+    created_at: Instant,
+    /// Breaks ties between entries created at the same instant in the eviction order
+    seq: u64,
+    /// Weight of the value once it is set
+    bytes: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Entries<K, V> {
+    map: HashMap<K, Arc<Entry<V>>>,
+    /// Keys of `map` from the oldest entry to the newest one
+    order: BTreeMap<(Instant, u64), K>,
+    next_seq: u64,
+    /// Sum of the weights of the entries
+    bytes: usize,
+}
+
+impl<K, V> Default for Entries<K, V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            order: BTreeMap::new(),
+            next_seq: 0,
+            bytes: 0,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V> Entries<K, V> {
+    fn insert(&mut self, key: K, value: OnceCell<V>, created_at: Instant) -> Arc<Entry<V>> {
+        let entry = Arc::new(Entry {
+            value,
+            created_at,
+            seq: self.next_seq,
+            bytes: AtomicUsize::new(0),
+        });
+        self.next_seq += 1;
+
+        self.order.insert((created_at, entry.seq), key.clone());
+        if let Some(replaced) = self.map.insert(key, Arc::clone(&entry)) {
+            self.forget(&replaced);
+        }
+
+        entry
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(removed) = self.map.remove(key) {
+            self.forget(&removed);
+        }
+    }
+
+    /// Drop a removed entry from the eviction order and the total weight
+    fn forget(&mut self, entry: &Entry<V>) {
+        self.order.remove(&(entry.created_at, entry.seq));
+        self.bytes -= entry.bytes.load(Ordering::Relaxed);
+    }
+
+    /// Whether `entry` is still the cached entry of `key`
+    fn is_current(&self, key: &K, entry: &Arc<Entry<V>>) -> bool {
+        self.map
+            .get(key)
+            .is_some_and(|current| Arc::ptr_eq(current, entry))
+    }
+}
+
+/// Bounded cache whose entries expire after a fixed time
+#[derive(Debug)]
+pub(crate) struct TtlCache<K, V> {
+    entries: Mutex<Entries<K, V>>,
+    capacity: usize,
+    /// Maximum sum of the weights of the values
+    max_bytes: usize,
+    weigh: fn(&V) -> usize,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub(crate) struct CacheStats {
+    pub(crate) entries: usize,
+    pub(crate) capacity: usize,
+    pub(crate) bytes: usize,
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TtlCache<K, V> {
+    pub(crate) fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::default(),
+            capacity,
+            max_bytes: usize::MAX,
+            weigh: |_| 0,
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Also bound the cache by the sum of the weights of its values
+    pub(crate) fn with_max_bytes(mut self, max_bytes: usize, weigh: fn(&V) -> usize) -> Self {
+        self.max_bytes = max_bytes;
+        self.weigh = weigh;
+        self
+    }
+
+    /// Value of `key`, looked up with `init` when it is missing or expired.
+    ///
+    /// Concurrent lookups of the same key wait for a single `init`. Failures are not cached.
+    /// `on_insert` is called with a new value once it is cached, unless the entry was
+    /// invalidated during `init`. It runs under the lock taken by invalidations.
+    pub(crate) async fn get_or_try_init<E, F, Fut>(
+        &self,
+        key: K,
+        init: F,
+        on_insert: impl FnOnce(&V),
+    ) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let entry = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.map.get(&key) {
+                Some(entry) if entry.created_at.elapsed() < self.ttl => Arc::clone(entry),
+                _ => {
+                    let entry = entries.insert(key.clone(), OnceCell::new(), Instant::now());
+                    self.evict(&mut entries);
+                    entry
+                }
+            }
+        };
+
+        if let Some(value) = entry.value.get() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(value.clone());
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let mut initialized = false;
+        let value = entry
+            .value
+            .get_or_try_init(|| {
+                initialized = true;
+                init()
+            })
+            .await?
+            .clone();
+
+        if initialized {
+            let mut entries = self.entries.lock().unwrap();
+            if entries.is_current(&key, &entry) {
+                let bytes = (self.weigh)(&value);
+                entry.bytes.store(bytes, Ordering::Relaxed);
+                entries.bytes += bytes;
+                self.evict(&mut entries);
+                // A value larger than the cache was evicted right away
+                if entries.is_current(&key, &entry) {
+                    on_insert(&value);
+                }
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Value of `key`, unless it is missing or expired
+    pub(crate) fn get(&self, key: &K) -> Option<V> {
+        let value = self
+            .entries
+            .lock()
+            .unwrap()
+            .map
+            .get(key)
+            .filter(|entry| entry.created_at.elapsed() < self.ttl)
+            .and_then(|entry| entry.value.get().cloned());
+
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        value
+    }
+
+    /// Insert a value which was just looked up
+    pub(crate) fn insert(&self, key: K, value: V) {
+        self.insert_aged(key, value, Duration::ZERO);
+    }
+
+    /// Insert a value looked up `age` ago, unless it already expired
+    pub(crate) fn insert_aged(&self, key: K, value: V, age: Duration) -> bool {
+        if age >= self.ttl {
+            return false;
+        }
+        let Some(created_at) = Instant::now().checked_sub(age) else {
+            return false;
+        };
+
+        let bytes = (self.weigh)(&value);
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.insert(key, OnceCell::new_with(Some(value)), created_at);
+        entry.bytes.store(bytes, Ordering::Relaxed);
+        entries.bytes += bytes;
+        self.evict(&mut entries);
+
+        true
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub(crate) fn invalidate(&self, key: &K) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    /// Remove the entries whose key matches `predicate`
+    pub(crate) fn invalidate_matching(&self, predicate: impl Fn(&K) -> bool) {
+        let mut entries = self.entries.lock().unwrap();
+        let matching = entries
+            .map
+            .keys()
+            .filter(|key| predicate(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in matching {
+            entries.remove(&key);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let entries = self.entries.lock().unwrap();
+        CacheStats {
+            entries: entries.map.len(),
+            capacity: self.capacity,
+            bytes: entries.bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Drop the oldest entries while they are expired, or the cache is over its bounds
+    fn evict(&self, entries: &mut Entries<K, V>) {
+        while let Some(((created_at, _), oldest)) = entries.order.first_key_value() {
+            let is_over = entries.map.len() > self.capacity || entries.bytes > self.max_bytes;
+            if !is_over && created_at.elapsed() < self.ttl {
+                break;
+            }
+            let oldest = oldest.clone();
+            entries.remove(&oldest);
+        }
+    }
+}
+
+/// Caches shared by all chains
+#[derive(Debug)]
+pub(crate) struct Caches {
+    /// Token URI and metadata keyed by chain id, contract and token id
+    pub(crate) tokens: TtlCache<(u64, Address, U256), Arc<TokenInfo>>,
+    /// Collection details keyed by chain id and contract
+    pub(crate) collections: TtlCache<(u64, Address), TokenData>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CachesStats {
+    pub(crate) tokens: CacheStats,
+    pub(crate) collections: CacheStats,
+}
+
+impl Caches {
+    pub(crate) fn new(config: &data::Cache) -> Self {
+        Self {
+            tokens: TtlCache::new(
+                config.token_capacity,
+                Duration::from_secs(config.token_ttl_secs),
+            )
+            .with_max_bytes(config.token_max_bytes, |info| info.weight()),
+            collections: TtlCache::new(
+                config.collection_capacity,
+                Duration::from_secs(config.collection_ttl_secs),
+            ),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CachesStats {
+        CachesStats {
+            tokens: self.tokens.stats(),
+            collections: self.collections.stats(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    fn keys(cache: &TtlCache<u32, String>) -> Vec<u32> {
+        let mut keys = (0..10)
+            .filter(|key| cache.get(key).is_some())
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn evicts_the_oldest_entries() {
+        let cache = TtlCache::new(3, Duration::from_secs(60));
+        cache.insert_aged(1, "a".to_owned(), Duration::from_secs(10));
+        cache.insert_aged(2, "b".to_owned(), Duration::from_secs(30));
+        cache.insert(3, "c".to_owned());
+        cache.insert(4, "d".to_owned());
+        assert_eq!(keys(&cache), [1, 3, 4]);
+
+        // Replacing an entry makes it the newest
+        cache.insert(1, "e".to_owned());
+        cache.insert(5, "f".to_owned());
+        assert_eq!(keys(&cache), [1, 4, 5]);
+    }
+
+    #[test]
+    fn bounds_the_weight_of_the_values() {
+        let cache = TtlCache::new(10, Duration::from_secs(60)).with_max_bytes(6, String::len);
+        cache.insert(1, "aaa".to_owned());
+        cache.insert(2, "bbb".to_owned());
+        cache.insert(3, "cc".to_owned());
+        assert_eq!(keys(&cache), [2, 3]);
+        assert_eq!(cache.stats().bytes, 5);
+
+        cache.invalidate_matching(|key| *key == 2);
+        assert_eq!(cache.stats().bytes, 2);
+
+        // Values larger than the cache are not kept
+        cache.insert(4, "dddddddd".to_owned());
+        assert!(keys(&cache).is_empty());
+        assert_eq!(cache.stats().bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn skips_values_invalidated_during_their_lookup() {
+        let cache = TtlCache::new(10, Duration::from_secs(60));
+
+        let mut inserted = vec![];
+        let value = cache
+            .get_or_try_init(
+                1,
+                || async {
+                    cache.invalidate(&1);
+                    Ok::<_, Infallible>("stale".to_owned())
+                },
+                |value: &String| inserted.push(value.clone()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(value, "stale");
+        assert!(inserted.is_empty());
+        assert_eq!(cache.get(&1), None);
+
+        let value = cache
+            .get_or_try_init(
+                1,
+                || async { Ok::<_, Infallible>("fresh".to_owned()) },
+                |value: &String| inserted.push(value.clone()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(value, "fresh");
+        assert_eq!(inserted, ["fresh"]);
+        assert_eq!(cache.get(&1).as_deref(), Some("fresh"));
+    }
+}
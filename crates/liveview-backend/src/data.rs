@@ -2,28 +2,227 @@ use alloy::primitives::Address;
 use serde::Deserialize;
 use url::Url;
 
+use crate::blocks::{Confirmation, MAX_CONFIRMATIONS};
+
 #[derive(Deserialize)]
-pub(crate) enum ChainType {
-    Mainnet,
-    Base,
-    Arbitrum,
-    Optimism,
-    Polygon,
-    Bsc,
+pub(crate) struct Rpc {
+    pub(crate) url: Url,
+    /// Endpoints with a lower priority are preferred
+    #[serde(default)]
+    pub(crate) priority: u32,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogSourceMode {
+    Subscribe,
+    Poll,
 }
 
 #[derive(Deserialize)]
 pub(crate) struct Chain {
-    pub(crate) rpc_url: Url,
+    /// Name used by clients to select the chain (e.g. "mainnet", "base-sepolia")
+    pub(crate) name: String,
+    pub(crate) chain_id: u64,
+    pub(crate) rpc_urls: Vec<Rpc>,
     pub(crate) multicall_address: Address,
+    /// Defaults to subscribing when a websocket RPC URL is configured and polling otherwise
+    pub(crate) log_source: Option<LogSourceMode>,
+    /// Interval between `eth_getLogs` polls in milliseconds
+    #[serde(default = "default_poll_interval_ms")]
+    pub(crate) poll_interval_ms: u64,
+    /// Confirmation policy of subscriptions which don't request one
+    #[serde(default)]
+    pub(crate) confirmation: Confirmation,
+}
+
+impl Chain {
+    /// Reject settings which are wrong whatever the RPC reports
+    pub(crate) fn validate(&self) -> eyre::Result<()> {
+        if self.rpc_urls.is_empty() {
+            eyre::bail!("No RPC URL configured for {}", self.name);
+        }
+
+        if !self.confirmation.is_valid() {
+            eyre::bail!(
+                "Confirmation of {} waits for more than {} blocks",
+                self.name,
+                MAX_CONFIRMATIONS
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn default_poll_interval_ms() -> u64 {
+    2000
 }
 
 #[derive(Deserialize)]
 pub(crate) struct Data {
-    pub(crate) mainnet: Chain,
-    pub(crate) base: Chain,
-    pub(crate) arbitrum: Chain,
-    pub(crate) optimism: Chain,
-    pub(crate) polygon: Chain,
-    pub(crate) bsc: Chain,
+    pub(crate) chains: Vec<Chain>,
+    /// Gateways resolving IPFS, IPNS and Arweave URIs
+    #[serde(default)]
+    pub(crate) gateways: Gateways,
+    /// Limits of the HTTP client fetching metadata
+    #[serde(default)]
+    pub(crate) fetcher: Fetcher,
+    #[serde(default)]
+    pub(crate) cache: Cache,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Gateway {
+    pub(crate) url: Url,
+    /// Private gateways, like a local node, are used for fetching but never sent to clients
+    #[serde(default = "default_public")]
+    pub(crate) public: bool,
+}
+
+fn default_public() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum GatewayStrategy {
+    /// Try the gateways one after the other
+    #[default]
+    Failover,
+    /// Query all gateways at once and keep the first response
+    Race,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Gateways {
+    /// IPFS gateways in order of preference, also used for IPNS
+    #[serde(default = "default_ipfs_gateways")]
+    pub(crate) ipfs: Vec<Gateway>,
+    /// Arweave gateways in order of preference
+    #[serde(default = "default_arweave_gateways")]
+    pub(crate) arweave: Vec<Gateway>,
+    #[serde(default)]
+    pub(crate) strategy: GatewayStrategy,
+    /// Timeout of a single gateway request in milliseconds
+    #[serde(default = "default_gateway_timeout_ms")]
+    pub(crate) timeout_ms: u64,
+}
+
+impl Default for Gateways {
+    fn default() -> Self {
+        Self {
+            ipfs: default_ipfs_gateways(),
+            arweave: default_arweave_gateways(),
+            strategy: GatewayStrategy::default(),
+            timeout_ms: default_gateway_timeout_ms(),
+        }
+    }
+}
+
+fn default_ipfs_gateways() -> Vec<Gateway> {
+    vec![Gateway {
+        url: "https://ipfs.io".parse().unwrap(),
+        public: true,
+    }]
+}
+
+fn default_arweave_gateways() -> Vec<Gateway> {
+    vec![Gateway {
+        url: "https://arweave.net".parse().unwrap(),
+        public: true,
+    }]
+}
+
+fn default_gateway_timeout_ms() -> u64 {
+    10_000
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Fetcher {
+    #[serde(default = "default_connect_timeout_ms")]
+    pub(crate) connect_timeout_ms: u64,
+    /// Maximum time between two reads of a response
+    #[serde(default = "default_read_timeout_ms")]
+    pub(crate) read_timeout_ms: u64,
+    #[serde(default = "default_max_body_bytes")]
+    pub(crate) max_body_bytes: usize,
+    #[serde(default = "default_max_redirects")]
+    pub(crate) max_redirects: usize,
+}
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: default_connect_timeout_ms(),
+            read_timeout_ms: default_read_timeout_ms(),
+            max_body_bytes: default_max_body_bytes(),
+            max_redirects: default_max_redirects(),
+        }
+    }
+}
+
+fn default_connect_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_read_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_max_body_bytes() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_max_redirects() -> usize {
+    3
+}
+
+#[derive(Deserialize, Clone)]
+pub(crate) struct Cache {
+    /// Maximum number of tokens whose metadata is cached
+    #[serde(default = "default_token_capacity")]
+    pub(crate) token_capacity: usize,
+    #[serde(default = "default_token_ttl_secs")]
+    pub(crate) token_ttl_secs: u64,
+    /// Maximum size of the cached token URIs and metadata, including the metadata JSON as served
+    #[serde(default = "default_token_max_bytes")]
+    pub(crate) token_max_bytes: usize,
+    /// Maximum number of contracts whose name, symbol and standard are cached
+    #[serde(default = "default_collection_capacity")]
+    pub(crate) collection_capacity: usize,
+    #[serde(default = "default_collection_ttl_secs")]
+    pub(crate) collection_ttl_secs: u64,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            token_capacity: default_token_capacity(),
+            token_ttl_secs: default_token_ttl_secs(),
+            token_max_bytes: default_token_max_bytes(),
+            collection_capacity: default_collection_capacity(),
+            collection_ttl_secs: default_collection_ttl_secs(),
+        }
+    }
+}
+
+fn default_token_capacity() -> usize {
+    10_000
+}
+
+fn default_token_ttl_secs() -> u64 {
+    3600
+}
+
+fn default_token_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_collection_capacity() -> usize {
+    1000
+}
+
+fn default_collection_ttl_secs() -> u64 {
+    86_400
 }
@@ -0,0 +1,388 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    redirect, StatusCode,
+};
+use serde_json::Value;
+use url::{Host, Origin, Url};
+
+use crate::data;
+
+/// Why a metadata document was not fetched
+#[derive(Debug)]
+pub(crate) enum FetchError {
+    /// The contract did not return a token URI
+    MissingTokenUri,
+    /// The URI uses a scheme metadata can't be fetched from
+    UnsupportedUri,
+    /// A `data:` URI which is not valid base64 or percent-encoding
+    InvalidDataUri,
+    /// The destination resolves to a private, loopback or link-local address
+    BlockedDestination(IpAddr),
+    /// A gateway redirected to a host of the configured gateways, which is not trusted
+    BlockedRedirect,
+    TooManyRedirects(usize),
+    Timeout,
+    Status(StatusCode),
+    UnexpectedContentType(String),
+    /// The body exceeds the configured maximum size in bytes
+    TooLarge(usize),
+    InvalidJson(serde_json::Error),
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchError::MissingTokenUri => write!(f, "Failed to fetch token URI"),
+            FetchError::UnsupportedUri => write!(f, "Unsupported metadata URI"),
+            FetchError::InvalidDataUri => write!(f, "Invalid data URI"),
+            FetchError::BlockedDestination(ip) => {
+                write!(
+                    f,
+                    "Destination {ip} is a private, loopback or link-local address"
+                )
+            }
+            FetchError::BlockedRedirect => write!(f, "Redirect to a gateway host"),
+            FetchError::TooManyRedirects(max) => write!(f, "More than {max} redirects"),
+            FetchError::Timeout => write!(f, "Request timed out"),
+            FetchError::Status(status) => write!(f, "Unexpected status {status}"),
+            FetchError::UnexpectedContentType(content_type) => {
+                write!(f, "Unexpected content type {content_type}")
+            }
+            FetchError::TooLarge(max) => write!(f, "Body is larger than {max} bytes"),
+            FetchError::InvalidJson(err) => write!(f, "Invalid JSON: {err}"),
+            FetchError::Request(err) => write!(f, "Request failed: {err}"),
+        }
+    }
+}
+
+impl Error for FetchError {}
+
+/// Rejections raised inside reqwest, by the DNS resolver and the redirect policy
+#[derive(Debug, Clone, Copy)]
+enum Rejection {
+    BlockedDestination(IpAddr),
+    BlockedRedirect,
+    TooManyRedirects(usize),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::BlockedDestination(ip) => write!(f, "Blocked destination {ip}"),
+            Rejection::BlockedRedirect => write!(f, "Blocked redirect to a gateway host"),
+            Rejection::TooManyRedirects(max) => write!(f, "More than {max} redirects"),
+        }
+    }
+}
+
+impl Error for Rejection {}
+
+impl From<Rejection> for FetchError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::BlockedDestination(ip) => FetchError::BlockedDestination(ip),
+            Rejection::BlockedRedirect => FetchError::BlockedRedirect,
+            Rejection::TooManyRedirects(max) => FetchError::TooManyRedirects(max),
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        // Rejections are wrapped in the errors of reqwest and hyper
+        let mut source = err.source();
+        while let Some(inner) = source {
+            if let Some(rejection) = inner.downcast_ref::<Rejection>() {
+                return FetchError::from(*rejection);
+            }
+            source = inner.source();
+        }
+
+        if err.is_timeout() {
+            FetchError::Timeout
+        } else {
+            FetchError::Request(err)
+        }
+    }
+}
+
+/// Whether requests to `ip` could reach our own network
+fn is_blocked(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_multicast()
+                // "This network", 0.0.0.0/8
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10
+                || (a == 100 && (b & 0xc0) == 64)
+                // Benchmarking, 198.18.0.0/15
+                || (a == 198 && (b & 0xfe) == 18)
+                // Reserved and broadcast, 240.0.0.0/4
+                || a >= 240
+        }
+        IpAddr::V6(ip) => {
+            // IPv4-mapped `::ffff:a.b.c.d` and IPv4-compatible `::a.b.c.d`
+            if let Some(ipv4) = ip.to_ipv4() {
+                return is_blocked(IpAddr::V4(ipv4));
+            }
+            // NAT64 `64:ff9b::/96` reaches the embedded IPv4 address
+            if ip.segments()[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., a, b, c, d] = ip.octets();
+                return is_blocked(IpAddr::V4(Ipv4Addr::new(a, b, c, d)));
+            }
+
+            ip.is_loopback()
+                || ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                // Local-use NAT64, 64:ff9b:1::/48
+                || ip.segments()[..3] == [0x64, 0xff9b, 1]
+        }
+    }
+}
+
+/// Reject IP literal hosts, which reqwest connects to without resolving
+fn check_host(url: &Url) -> Result<(), Rejection> {
+    let ip = match url.host() {
+        Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+        Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        Some(Host::Domain(_)) | None => return Ok(()),
+    };
+    if is_blocked(ip) {
+        return Err(Rejection::BlockedDestination(ip));
+    }
+
+    Ok(())
+}
+
+/// Resolves host names and rejects those pointing at blocked addresses
+struct GuardedResolver {
+    trusted_hosts: Arc<HashSet<String>>,
+}
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_owned();
+        let is_trusted = self.trusted_hosts.contains(&host);
+
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .collect::<Vec<SocketAddr>>();
+            if !is_trusted {
+                // A single blocked address is enough to reject the host
+                if let Some(addr) = addrs.iter().find(|addr| is_blocked(addr.ip())) {
+                    return Err(Box::new(Rejection::BlockedDestination(addr.ip()))
+                        as Box<dyn Error + Send + Sync>);
+                }
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// HTTP client whose resolver rejects blocked addresses, except for the hosts in `trusted_hosts`
+fn build_client(
+    config: &data::Fetcher,
+    trusted_hosts: HashSet<String>,
+) -> reqwest::Result<reqwest::Client> {
+    let trusted_hosts = Arc::new(trusted_hosts);
+    let max_redirects = config.max_redirects;
+    let redirect_hosts = Arc::clone(&trusted_hosts);
+
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .read_timeout(Duration::from_millis(config.read_timeout_ms))
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                return attempt.error(Rejection::TooManyRedirects(max_redirects));
+            }
+            // Redirects are never trusted, the resolver would let them reach any port of a gateway host
+            if attempt
+                .url()
+                .host_str()
+                .is_some_and(|host| redirect_hosts.contains(host))
+            {
+                return attempt.error(Rejection::BlockedRedirect);
+            }
+            match check_host(attempt.url()) {
+                Ok(()) => attempt.follow(),
+                Err(rejection) => attempt.error(rejection),
+            }
+        }))
+        .dns_resolver(Arc::new(GuardedResolver { trusted_hosts }))
+        // Proxies would resolve the host names themselves
+        .no_proxy()
+        .build()
+}
+
+/// HTTP clients shared by all metadata requests
+#[derive(Debug)]
+pub(crate) struct Fetcher {
+    /// Client for URLs taken from token URIs, which never reach our own network
+    client: reqwest::Client,
+    /// Client for URLs built for the configured gateways, which may live on our own network
+    gateway_client: reqwest::Client,
+    gateway_origins: HashSet<Origin>,
+    max_body_bytes: usize,
+}
+
+impl Fetcher {
+    pub(crate) fn new(config: &data::Fetcher, gateways: &[Url]) -> reqwest::Result<Self> {
+        let gateway_hosts = gateways
+            .iter()
+            .filter_map(|url| url.host_str().map(str::to_owned))
+            .collect();
+
+        Ok(Self {
+            client: build_client(config, HashSet::new())?,
+            gateway_client: build_client(config, gateway_hosts)?,
+            gateway_origins: gateways.iter().map(Url::origin).collect(),
+            max_body_bytes: config.max_body_bytes,
+        })
+    }
+
+    /// Fetch a JSON document within `timeout`.
+    ///
+    /// `via_gateway` is only set for URLs built for a configured gateway, which are trusted when
+    /// their scheme, host and port match it. `refresh` asks gateways and CDNs for a fresh copy.
+    pub(crate) async fn get_json(
+        &self,
+        url: Url,
+        timeout: Duration,
+        refresh: bool,
+        via_gateway: bool,
+    ) -> Result<Value, FetchError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(FetchError::UnsupportedUri);
+        }
+        let client = if via_gateway && self.gateway_origins.contains(&url.origin()) {
+            &self.gateway_client
+        } else {
+            check_host(&url)?;
+            &self.client
+        };
+
+        let mut req = client.get(url).timeout(timeout);
+        if refresh {
+            req = req.header(CACHE_CONTROL, "no-cache");
+        }
+        let mut res = req.send().await?;
+
+        if !res.status().is_success() {
+            return Err(FetchError::Status(res.status()));
+        }
+
+        // Metadata is often served as plain text or without a content type
+        if let Some(content_type) = res.headers().get(CONTENT_TYPE) {
+            let content_type = content_type.to_str().unwrap_or_default();
+            let media_type = content_type
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_ascii_lowercase();
+            if !(media_type.contains("json")
+                || media_type == "text/plain"
+                || media_type == "application/octet-stream")
+            {
+                return Err(FetchError::UnexpectedContentType(content_type.to_owned()));
+            }
+        }
+
+        if res
+            .content_length()
+            .is_some_and(|length| length > self.max_body_bytes as u64)
+        {
+            return Err(FetchError::TooLarge(self.max_body_bytes));
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            if body.len() + chunk.len() > self.max_body_bytes {
+                return Err(FetchError::TooLarge(self.max_body_bytes));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        serde_json::from_slice(&body).map_err(FetchError::InvalidJson)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocked(ip: &str) -> bool {
+        is_blocked(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn blocks_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "169.254.169.254",
+            "0.1.2.3",
+            "100.64.0.1",
+            "198.19.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+            "64:ff9b:1::1",
+        ] {
+            assert!(blocked(ip), "{ip} should be blocked");
+        }
+    }
+
+    #[test]
+    fn allows_public_addresses() {
+        for ip in [
+            "1.1.1.1",
+            "198.20.0.1",
+            "2606:4700:4700::1111",
+            "64:ff9b::101:101",
+        ] {
+            assert!(!blocked(ip), "{ip} should be allowed");
+        }
+    }
+
+    /// Only URLs built for the local gateway may reach it, never other ports of its host
+    #[tokio::test]
+    async fn trusts_only_the_gateway_origin() {
+        let gateway = Url::parse("http://127.0.0.1:8080").unwrap();
+        let fetcher = Fetcher::new(&data::Fetcher::default(), &[gateway]).unwrap();
+        let timeout = Duration::from_secs(1);
+
+        for (url, via_gateway) in [
+            ("http://127.0.0.1:6379/", false),
+            ("http://127.0.0.1:5001/api/v0/version", true),
+            ("http://127.0.0.1:8080/ipfs/cid", false),
+        ] {
+            let url = Url::parse(url).unwrap();
+            let res = fetcher.get_json(url, timeout, false, via_gateway).await;
+            assert!(matches!(res, Err(FetchError::BlockedDestination(_))));
+        }
+    }
+}
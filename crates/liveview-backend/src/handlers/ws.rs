@@ -1,69 +1,406 @@
 use std::{
-    collections::{HashMap, HashSet},
-    sync::Arc,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
 };
 
-use alloy::{
-    primitives::{Address, FixedBytes, U256},
-    providers::Provider,
-    rpc::types::Filter,
-    sol_types::{SolCall, SolEvent},
-};
-use chrono::{DateTime, Utc};
-use futures_util::StreamExt;
+use alloy::{primitives::Address, providers::Provider, rpc::types::Filter};
+use futures_util::{stream, stream::BoxStream, StreamExt};
 use serde::{Deserialize, Serialize};
 use socketioxide::{
-    extract::{Data as SocketData, SocketRef, State as SocketState},
+    extract::{AckSender, Data as SocketData, SocketRef, State as SocketState},
     socket::Sid as SocketSid,
 };
-use tokio::sync::watch;
-use tracing::{debug, instrument};
-use url::Url;
+use tokio::{sync::watch, task::AbortHandle};
+use tracing::{debug, instrument, warn};
 
 use crate::{
-    data::ChainType,
-    interfaces::{Multicall, ERC721},
-    state::AppState,
-    utils::{self, MetadataType},
+    blocks::{Confirmation, Heads, MAX_CONFIRMATIONS},
+    history::{self, Backfill},
+    logs::StreamStatus,
+    metadata,
+    positions::{self, PositionEvent},
+    state::{AppState, ChainState},
+    subscriptions::{self, FeedEvent, FeedKind, MetadataUpdate, Revert, TokenEvent, Transfer},
+    tokens::{self, InvalidReason, TokenData, TokenStandard, Validation, ValidationStatus},
 };
 
+type SubscriptionId = u64;
+
+/// Upper bound of the unconfirmed events held per subscription
+const MAX_PENDING_EVENTS: usize = 1024;
+
 #[derive(Deserialize)]
 struct RequestData {
-    chain: ChainType,
+    chain: String,
     addresses: Vec<Address>,
+    /// Historical transfers to send before live ones
+    backfill: Option<Backfill>,
+    /// Defaults to the confirmation policy of the chain
+    confirmation: Option<Confirmation>,
+    /// Also send transfers in the `pending` phase before they are confirmed
+    #[serde(default)]
+    emit_pending: bool,
+    /// Also stream the liquidity changes of position manager NFTs, enriched with their positions
+    #[serde(default)]
+    positions: bool,
+}
+
+#[derive(Deserialize)]
+struct UnsubscribeData {
+    subscription_id: SubscriptionId,
+}
+
+#[derive(Deserialize)]
+struct UpdateData {
+    subscription_id: SubscriptionId,
+    addresses: Vec<Address>,
+}
+
+/// Acknowledgement of `request`, `update` and `unsubscribe` events
+#[derive(Serialize)]
+#[serde(untagged)]
+enum AckData<'a> {
+    Ok {
+        subscription_id: SubscriptionId,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        validation: Option<&'a [Validation]>,
+    },
+    Error {
+        message: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        validation: Option<&'a [Validation]>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Phase {
+    Pending,
+    Confirmed,
 }
 
 #[derive(Serialize)]
-struct ResponseData {
+struct ResponseData<'a> {
     id: SocketSid,
+    subscription_id: SubscriptionId,
+    #[serde(flatten)]
+    transfer: &'a Transfer,
+    /// Whether the transfer was sent as part of the requested backfill
+    historical: bool,
+    phase: Phase,
+}
+
+#[derive(Serialize)]
+struct PositionData<'a> {
+    id: SocketSid,
+    subscription_id: SubscriptionId,
+    #[serde(flatten)]
+    event: &'a PositionEvent,
+    historical: bool,
+    phase: Phase,
+}
+
+#[derive(Serialize)]
+struct MetadataUpdateData<'a> {
+    id: SocketSid,
+    subscription_id: SubscriptionId,
+    #[serde(flatten)]
+    update: &'a MetadataUpdate,
+    historical: bool,
+    phase: Phase,
+}
+
+#[derive(Serialize)]
+struct RevertData<'a> {
+    id: SocketSid,
+    subscription_id: SubscriptionId,
+    #[serde(flatten)]
+    revert: &'a Revert,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorData<'a> {
+    id: SocketSid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscription_id: Option<SubscriptionId>,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    validation: Option<&'a [Validation]>,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusData {
+    id: SocketSid,
+    subscription_id: SubscriptionId,
     address: Address,
-    name: String,
-    symbol: String,
-    from: Address,
-    to: Address,
-    token_id: U256,
-    image: Option<String>,
-    image_type: Option<MetadataType>,
-    block_number: u64,
-    transaction_hash: FixedBytes<32>,
-    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    status: StreamStatus,
 }
 
 #[derive(Debug, Serialize)]
-struct ErrorData {
+struct LaggedData {
     id: SocketSid,
-    message: String,
+    subscription_id: SubscriptionId,
+    address: Address,
+    skipped: u64,
 }
 
-#[derive(Debug)]
-struct TokenData {
-    name: String,
-    symbol: String,
+#[derive(Debug, Serialize)]
+struct BackfillCompleteData {
+    id: SocketSid,
+    subscription_id: SubscriptionId,
+    count: usize,
+    /// First block whose history was sent
+    from_block: u64,
+    to_block: u64,
+    /// Whether older history was left out because of the backfill limits
+    truncated: bool,
 }
 
-#[derive(Deserialize)]
-struct Metadata {
-    image: String,
+/// History to replay before the live events of a subscription
+struct History {
+    chain_state: Arc<ChainState>,
+    token_data: HashMap<Address, TokenData>,
+    kind: FeedKind,
+    backfill: Backfill,
+}
+
+struct ActiveSubscription {
+    chain_state: Arc<ChainState>,
+    kind: FeedKind,
+    emitter: Emitter,
+    task: AbortHandle,
+}
+
+/// An event held back until it is confirmed
+struct PendingEvent {
+    event: TokenEvent,
+    historical: bool,
+}
+
+/// Sends the events of a subscription to its socket according to its confirmation policy.
+/// Clones share the pending events, so they survive an `update` of the subscription.
+#[derive(Clone)]
+struct Emitter {
+    socket: SocketRef,
+    subscription_id: SubscriptionId,
+    confirmation: Confirmation,
+    emit_pending: bool,
+    /// Unconfirmed events in the order they were received
+    pending: Arc<Mutex<VecDeque<PendingEvent>>>,
+}
+
+impl Emitter {
+    fn new(
+        socket: SocketRef,
+        subscription_id: SubscriptionId,
+        confirmation: Confirmation,
+        emit_pending: bool,
+    ) -> Self {
+        Self {
+            socket,
+            subscription_id,
+            confirmation,
+            emit_pending,
+            pending: Arc::default(),
+        }
+    }
+
+    fn event(&self, event: TokenEvent, historical: bool, heads: &Heads) {
+        let mut pending = self.pending.lock().unwrap();
+
+        // Events are confirmed in order, so a new one waits behind the pending ones
+        if pending.is_empty() && self.confirmation.is_met(event.block_number(), heads) {
+            self.emit_event(&event, historical, Phase::Confirmed);
+            return;
+        }
+
+        if self.emit_pending {
+            self.emit_event(&event, historical, Phase::Pending);
+        }
+        if pending.len() >= MAX_PENDING_EVENTS {
+            if let Some(dropped) = pending.pop_front() {
+                warn!(?self.socket.id, self.subscription_id, "Dropping the oldest pending event");
+                // Identifies the event like a revert, it will never be confirmed
+                let dropped_data = RevertData {
+                    id: self.socket.id,
+                    subscription_id: self.subscription_id,
+                    revert: &dropped.event.revert(),
+                };
+                self.socket.emit("dropped", &dropped_data).ok();
+            }
+        }
+        pending.push_back(PendingEvent { event, historical });
+    }
+
+    /// Send the pending events which are now confirmed
+    fn confirm(&self, heads: &Heads) {
+        let mut pending = self.pending.lock().unwrap();
+        while pending.front().is_some_and(|pending| {
+            self.confirmation
+                .is_met(pending.event.block_number(), heads)
+        }) {
+            let Some(confirmed) = pending.pop_front() else {
+                break;
+            };
+            self.emit_event(&confirmed.event, confirmed.historical, Phase::Confirmed);
+        }
+    }
+
+    fn revert(&self, revert: &Revert) {
+        let mut pending = self.pending.lock().unwrap();
+        let position = pending
+            .iter()
+            .position(|pending| pending.event.revert() == *revert);
+
+        // An event which was never sent doesn't have to be reverted
+        if position
+            .and_then(|position| pending.remove(position))
+            .is_some()
+            && !self.emit_pending
+        {
+            return;
+        }
+
+        let revert_data = RevertData {
+            id: self.socket.id,
+            subscription_id: self.subscription_id,
+            revert,
+        };
+        self.socket.emit("revert", &revert_data).ok();
+    }
+
+    fn emit_event(&self, event: &TokenEvent, historical: bool, phase: Phase) {
+        match event {
+            TokenEvent::Transfer(transfer) => {
+                let response_data = ResponseData {
+                    id: self.socket.id,
+                    subscription_id: self.subscription_id,
+                    transfer,
+                    historical,
+                    phase,
+                };
+                self.socket.emit("response", &response_data).ok();
+            }
+            TokenEvent::Position(event) => {
+                let position_data = PositionData {
+                    id: self.socket.id,
+                    subscription_id: self.subscription_id,
+                    event,
+                    historical,
+                    phase,
+                };
+                self.socket.emit("position", &position_data).ok();
+            }
+            TokenEvent::MetadataUpdate(update) => {
+                let update_data = MetadataUpdateData {
+                    id: self.socket.id,
+                    subscription_id: self.subscription_id,
+                    update,
+                    historical,
+                    phase,
+                };
+                self.socket.emit("metadata_update", &update_data).ok();
+            }
+        }
+    }
+}
+
+/// Subscriptions opened by a single socket
+#[derive(Default)]
+struct SocketSubscriptions {
+    next_id: AtomicU64,
+    active: Mutex<HashMap<SubscriptionId, ActiveSubscription>>,
+    /// Slots taken by subscriptions which are still being opened
+    reserved: AtomicUsize,
+}
+
+/// Slot of a subscription being opened, released when dropped
+struct Reservation<'a> {
+    subscriptions: &'a SocketSubscriptions,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.subscriptions.reserved.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl SocketSubscriptions {
+    /// Take a slot for a new subscription, unless `max` are already open or being opened
+    fn reserve(&self, max: usize) -> Option<Reservation<'_>> {
+        // Checked and taken under the lock, so that concurrent requests can't both take the last slot
+        let active = self.active.lock().unwrap();
+        if active.len() + self.reserved.load(Ordering::Relaxed) >= max {
+            return None;
+        }
+        self.reserved.fetch_add(1, Ordering::Relaxed);
+
+        Some(Reservation {
+            subscriptions: self,
+        })
+    }
+
+    /// Register a subscription, replacing and stopping the previous one with the same id
+    fn insert(&self, subscription_id: SubscriptionId, subscription: ActiveSubscription) {
+        let previous = self
+            .active
+            .lock()
+            .unwrap()
+            .insert(subscription_id, subscription);
+        if let Some(previous) = previous {
+            previous.task.abort();
+        }
+    }
+
+    /// Replace a subscription with the one built by `build`, unless it was removed meanwhile.
+    ///
+    /// `build` runs under the lock, so a concurrent `unsubscribe` can't be undone.
+    fn replace(
+        &self,
+        subscription_id: SubscriptionId,
+        build: impl FnOnce() -> ActiveSubscription,
+    ) -> bool {
+        let mut active = self.active.lock().unwrap();
+        let Some(current) = active.get_mut(&subscription_id) else {
+            return false;
+        };
+
+        let previous = std::mem::replace(current, build());
+        previous.task.abort();
+
+        true
+    }
+
+    fn remove(&self, subscription_id: SubscriptionId) -> bool {
+        match self.active.lock().unwrap().remove(&subscription_id) {
+            Some(subscription) => {
+                subscription.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn get(&self, subscription_id: SubscriptionId) -> Option<(Arc<ChainState>, FeedKind, Emitter)> {
+        let active = self.active.lock().unwrap();
+        active.get(&subscription_id).map(|subscription| {
+            (
+                Arc::clone(&subscription.chain_state),
+                subscription.kind,
+                subscription.emitter.clone(),
+            )
+        })
+    }
+
+    fn clear(&self) {
+        for (_, subscription) in self.active.lock().unwrap().drain() {
+            subscription.task.abort();
+        }
+    }
 }
 
 #[instrument(skip(state))]
@@ -71,279 +408,439 @@ pub(crate) async fn ws(socket: SocketRef, state: SocketState<Arc<AppState>>) {
     debug!(ns = socket.ns(), ?socket.id, "Socket.IO connected");
 
     let state = Arc::clone(&state);
+    let subscriptions = Arc::new(SocketSubscriptions::default());
+
+    // Stop every subscription of the socket on disconnection
+    socket.on_disconnect({
+        let subscriptions = Arc::clone(&subscriptions);
+        move |socket: SocketRef| {
+            debug!(?socket.id, "Socket disconnected");
+
+            subscriptions.clear();
+        }
+    });
+
+    socket.on("request", {
+        let subscriptions = Arc::clone(&subscriptions);
+        move |socket: SocketRef, SocketData::<RequestData>(data), ack: AckSender| async move {
+            let chain_state = match state.chain(&data.chain) {
+                Ok(chain_state) => chain_state,
+                Err(err) => return reply_error(&socket, ack, None, &err.to_string(), None),
+            };
 
-    socket.on(
-        "request",
-        |socket: SocketRef, SocketData::<RequestData>(data)| async move {
-            // debug!(?data, "Received event");
-
-            // Watch channel for disconnection
-            let (tx, mut rx) = watch::channel(());
-            let socket_id = socket.id;
-            socket.on_disconnect(move || {
-                debug!(?socket_id, "Socket disconnected");
+            let confirmation = data.confirmation.unwrap_or(chain_state.confirmation);
+            if !confirmation.is_valid() {
+                let message = format!("Confirmations are limited to {MAX_CONFIRMATIONS} blocks");
+                return reply_error(&socket, ack, None, &message, None);
+            }
+            if !confirmation.is_supported(&chain_state.blocks.heads().borrow()) {
+                let message =
+                    "The chain doesn't report the block the confirmation policy waits for";
+                return reply_error(&socket, ack, None, message, None);
+            }
 
-                tx.send(()).ok();
-            });
+            let Some(_reservation) = subscriptions.reserve(state.max_subscriptions) else {
+                let message = format!(
+                    "Too many subscriptions, at most {} are allowed",
+                    state.max_subscriptions
+                );
+                return reply_error(&socket, ack, None, &message, None);
+            };
 
-            // If there's no addresses
-            if data.addresses.is_empty() {
-                socket
-                    .emit(
-                        "error",
-                        &ErrorData {
-                            id: socket.id,
-                            message: "No addresses provided".to_owned(),
-                        },
-                    )
-                    .ok();
-
-                return;
+            let kind = if data.positions {
+                FeedKind::Positions
+            } else {
+                FeedKind::Transfers
+            };
+            let (feeds, validation) = match open_feeds(&chain_state, data.addresses, kind).await {
+                Ok(res) => res,
+                Err(message) => return reply_error(&socket, ack, None, &message, None),
+            };
+            if feeds.is_empty() {
+                let message = "No valid address provided";
+                return reply_error(&socket, ack, None, message, Some(&validation));
             }
 
-            // Remove duplicates
-            let data_addresses = data
-                .addresses
-                .to_vec()
-                .drain(..)
-                .collect::<HashSet<_>>()
-                .drain()
-                .collect::<Vec<_>>();
-
-            let chain_state = match data.chain {
-                ChainType::Mainnet => &state.mainnet,
-                ChainType::Base => &state.base,
-                ChainType::Arbitrum => &state.arbitrum,
-                ChainType::Optimism => &state.optimism,
-                ChainType::Polygon => &state.polygon,
-                ChainType::Bsc => &state.bsc,
-            };
+            let history = data.backfill.map(|backfill| History {
+                chain_state: Arc::clone(&chain_state),
+                token_data: validation
+                    .iter()
+                    .filter_map(|validation| Some((validation.address, validation.token_data()?)))
+                    .collect(),
+                kind,
+                backfill,
+            });
 
-            // Check if all addresses are correct
-            let multicall = Multicall::new(
-                chain_state.multicall_address,
-                Arc::clone(&chain_state.provider),
+            let subscription_id = subscriptions.next_id.fetch_add(1, Ordering::Relaxed);
+            let emitter = Emitter::new(
+                socket.clone(),
+                subscription_id,
+                confirmation,
+                data.emit_pending,
             );
+            let task = spawn_subscription(emitter.clone(), &chain_state, feeds, history);
+            subscriptions.insert(
+                subscription_id,
+                ActiveSubscription {
+                    chain_state,
+                    kind,
+                    emitter,
+                    task,
+                },
+            );
+            debug!(?socket.id, subscription_id, "Subscription created");
+
+            ack.send(&AckData::Ok {
+                subscription_id,
+                validation: Some(&validation),
+            })
+            .ok();
+        }
+    });
+
+    socket.on("update", {
+        let subscriptions = Arc::clone(&subscriptions);
+        move |socket: SocketRef, SocketData::<UpdateData>(data), ack: AckSender| async move {
+            let subscription_id = data.subscription_id;
+            let Some((chain_state, kind, emitter)) = subscriptions.get(subscription_id) else {
+                return reply_error(
+                    &socket,
+                    ack,
+                    Some(subscription_id),
+                    "Unknown subscription",
+                    None,
+                );
+            };
 
-            let mut calls = vec![];
-            for addr in &data_addresses {
-                let erc721 = ERC721::new(addr.to_owned(), Arc::clone(&chain_state.provider));
-
-                calls.push(Multicall::Call {
-                    target: addr.to_owned(),
-                    gasLimit: U256::MAX,
-                    callData: erc721
-                        .supportsInterface(FixedBytes(
-                            [0x80, 0xac, 0x58, 0xcd], /* ERC721.supportsInterface */
-                        ))
-                        .calldata()
-                        .to_owned(),
-                });
-                calls.push(Multicall::Call {
-                    target: addr.to_owned(),
-                    gasLimit: U256::MAX,
-                    callData: erc721.name().calldata().to_owned(),
-                });
-                calls.push(Multicall::Call {
-                    target: addr.to_owned(),
-                    gasLimit: U256::MAX,
-                    callData: erc721.symbol().calldata().to_owned(),
-                });
-            }
-
-            // Check all addresses for support of ERC721.supportsInterface in multicall
-            let multicall_res = match multicall.multicall(calls).call().await {
-                Ok(res) => res.returnData,
-                Err(_) => {
-                    socket
-                        .emit(
-                            "error",
-                            &ErrorData {
-                                id: socket.id,
-                                message: "Failed to call fetch data".to_owned(),
-                            },
-                        )
-                        .ok();
-
-                    return;
+            // The new feeds are opened before the old ones are released,
+            // so addresses present in both keep their upstream stream
+            let (feeds, validation) = match open_feeds(&chain_state, data.addresses, kind).await {
+                Ok(res) => res,
+                Err(message) => {
+                    return reply_error(&socket, ack, Some(subscription_id), &message, None);
                 }
             };
+            if feeds.is_empty() {
+                let message = "No valid address provided";
+                return reply_error(
+                    &socket,
+                    ack,
+                    Some(subscription_id),
+                    message,
+                    Some(&validation),
+                );
+            }
 
-            let mut token_data = HashMap::new();
-
-            // Check if all addresses support the interface
-            for (i, res) in multicall_res
-                /* 1 for supportsInterface, 1 for name, 1 for symbol */
-                .chunks(3)
-                .enumerate()
-            {
-                // First index is for supportsInterface call
-                let interface_data = res[0].returnData.to_owned();
-                let interface_res =
-                    match ERC721::supportsInterfaceCall::abi_decode_returns(&interface_data, false)
-                    {
-                        Ok(res) => res._0,
-                        Err(_) => false, // Error means that the address doesn't support the interface
-                    };
+            // The subscription may have been removed while the feeds were opened,
+            // the new feeds are then dropped
+            let replaced = subscriptions.replace(subscription_id, || ActiveSubscription {
+                task: spawn_subscription(emitter.clone(), &chain_state, feeds, None),
+                chain_state: Arc::clone(&chain_state),
+                kind,
+                emitter,
+            });
+            if !replaced {
+                return reply_error(
+                    &socket,
+                    ack,
+                    Some(subscription_id),
+                    "Unknown subscription",
+                    None,
+                );
+            }
+            debug!(?socket.id, subscription_id, "Subscription updated");
+
+            ack.send(&AckData::Ok {
+                subscription_id,
+                validation: Some(&validation),
+            })
+            .ok();
+        }
+    });
+
+    socket.on("unsubscribe", {
+        let subscriptions = Arc::clone(&subscriptions);
+        move |socket: SocketRef, SocketData::<UnsubscribeData>(data), ack: AckSender| async move {
+            let subscription_id = data.subscription_id;
+            if !subscriptions.remove(subscription_id) {
+                return reply_error(
+                    &socket,
+                    ack,
+                    Some(subscription_id),
+                    "Unknown subscription",
+                    None,
+                );
+            }
+            debug!(?socket.id, subscription_id, "Subscription removed");
+
+            ack.send(&AckData::Ok {
+                subscription_id,
+                validation: None,
+            })
+            .ok();
+        }
+    });
+}
 
-                if !interface_res {
-                    socket
-                        .emit(
-                            "error",
-                            &ErrorData {
-                                id: socket.id,
-                                message: "Invalid address provided".to_owned(),
-                            },
-                        )
-                        .ok();
-
-                    return;
-                }
+/// Report an error both as an `error` event and through the acknowledgement
+fn reply_error(
+    socket: &SocketRef,
+    ack: AckSender,
+    subscription_id: Option<SubscriptionId>,
+    message: &str,
+    validation: Option<&[Validation]>,
+) {
+    socket
+        .emit(
+            "error",
+            &ErrorData {
+                id: socket.id,
+                subscription_id,
+                message,
+                validation,
+            },
+        )
+        .ok();
+    ack.send(&AckData::Error {
+        message,
+        validation,
+    })
+    .ok();
+}
 
-                // Second index in for name
-                let name_data = res[1].returnData.to_owned();
-                let name_res = match ERC721::nameCall::abi_decode_returns(&name_data, false) {
-                    Ok(decode_res) => decode_res._0,
-                    Err(_) => {
-                        socket
-                            .emit(
-                                "error",
-                                &ErrorData {
-                                    id: socket.id,
-                                    message: "Invalid address provided".to_owned(),
-                                },
-                            )
-                            .ok();
-                        return;
-                    }
-                };
+/// Validate the addresses and subscribe to the shared feed of each valid one
+async fn open_feeds(
+    chain_state: &Arc<ChainState>,
+    addresses: Vec<Address>,
+    kind: FeedKind,
+) -> Result<(Vec<BoxStream<'static, FeedEvent>>, Vec<Validation>), String> {
+    // If there's no addresses
+    if addresses.is_empty() {
+        return Err("No addresses provided".to_owned());
+    }
+
+    // Remove duplicates
+    let addresses = addresses
+        .into_iter()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let mut validations = tokens::lookup(chain_state, &addresses).await;
+
+    let mut feeds = Vec::with_capacity(validations.len());
+    for validation in &mut validations {
+        let Some(token_data) = validation.token_data() else {
+            continue;
+        };
+
+        // Positions are NFTs of the position manager, other collections would fail every lookup
+        if kind == FeedKind::Positions
+            && (token_data.standard != TokenStandard::Erc721
+                || !positions::is_position_manager(chain_state, validation.address).await)
+        {
+            validation.status = ValidationStatus::invalid(InvalidReason::NotPositionManager);
+            continue;
+        }
+
+        let feed = match chain_state
+            .subscriptions
+            .watch(chain_state, validation.address, token_data, kind)
+            .await
+        {
+            Ok(feed) => feed,
+            Err(_) => return Err("Failed to subscribe to logs".to_owned()),
+        };
+        feeds.push(feed.into_stream());
+    }
+
+    Ok((feeds, validations))
+}
 
-                // Third index in for symbol
-                let symbol_data = res[2].returnData.to_owned();
-                let symbol_res = match ERC721::symbolCall::abi_decode_returns(&symbol_data, false) {
-                    Ok(decode_res) => decode_res._0,
-                    Err(_) => {
-                        socket
-                            .emit(
-                                "error",
-                                &ErrorData {
-                                    id: socket.id,
-                                    message: "Invalid address provided".to_owned(),
-                                },
-                            )
-                            .ok();
-                        return;
+/// Forward the events of the feeds to the socket until the subscription is stopped,
+/// after replaying the requested history
+fn spawn_subscription(
+    emitter: Emitter,
+    chain_state: &ChainState,
+    feeds: Vec<BoxStream<'static, FeedEvent>>,
+    history: Option<History>,
+) -> AbortHandle {
+    // The feeds are already subscribed, so live events buffer while the history is replayed
+    let mut stream = stream::select_all(feeds);
+    let mut heads = chain_state.blocks.heads();
+
+    let task = tokio::spawn(async move {
+        let seam = match history {
+            Some(history) => replay_history(&emitter, history, &heads).await,
+            None => None,
+        };
+
+        loop {
+            let event = tokio::select! {
+                event = stream.next() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                res = heads.changed() => {
+                    if res.is_err() {
+                        break;
                     }
-                };
+                    let current = *heads.borrow_and_update();
+                    emitter.confirm(&current);
+                    continue;
+                }
+            };
 
-                token_data.insert(
-                    data_addresses.to_owned()[i],
-                    TokenData {
-                        name: name_res,
-                        symbol: symbol_res,
-                    },
-                );
+            match event {
+                FeedEvent::Token(event) if is_replayed(seam, event.block_number()) => {}
+                FeedEvent::Token(event) => {
+                    let current = *heads.borrow();
+                    emitter.event(event, false, &current);
+                }
+                FeedEvent::Revert(revert) => emitter.revert(&revert),
+                FeedEvent::Status { address, status } => {
+                    let status_data = StatusData {
+                        id: emitter.socket.id,
+                        subscription_id: emitter.subscription_id,
+                        address,
+                        status,
+                    };
+                    emitter.socket.emit("status", &status_data).ok();
+                }
+                FeedEvent::Lagged { address, skipped } => {
+                    warn!(?emitter.socket.id, emitter.subscription_id, ?address, skipped, "Subscriber lagged behind");
+                    let lagged_data = LaggedData {
+                        id: emitter.socket.id,
+                        subscription_id: emitter.subscription_id,
+                        address,
+                        skipped,
+                    };
+                    emitter.socket.emit("lagged", &lagged_data).ok();
+                }
             }
+        }
+    });
 
-            // Create a subscription to blocks
-            // let sub = match chain_state.provider.subscribe_blocks().await {
-            let filter = Filter::new()
-                .address(data.addresses)
-                .event(ERC721::Transfer::SIGNATURE);
-
-            let sub = match chain_state.provider.subscribe_logs(&filter).await {
-                Ok(sub) => sub,
-                Err(_) => {
-                    socket
-                        .emit(
-                            "error",
-                            &ErrorData {
-                                id: socket.id,
-                                message: "Failed to subscribe to blocks".to_owned(),
-                            },
-                        )
-                        .ok();
-
-                    return;
-                }
+    task.abort_handle()
+}
+
+/// Whether a live event was already sent as part of the history ending at `seam`
+fn is_replayed(seam: Option<u64>, block_number: u64) -> bool {
+    seam.is_some_and(|seam| block_number <= seam)
+}
+
+/// Send the historical transfers of a subscription in order.
+/// Returns the last block covered by the history.
+async fn replay_history(
+    emitter: &Emitter,
+    history: History,
+    heads: &watch::Receiver<Heads>,
+) -> Option<u64> {
+    let socket = &emitter.socket;
+    let subscription_id = emitter.subscription_id;
+    let chain_state = &history.chain_state;
+    // Past metadata updates are left out, they would refetch and evict the metadata
+    // shared with every other subscriber and count towards the requested transfers
+    let update_events = metadata::update_events();
+    let filter = Filter::new()
+        .address(history.token_data.keys().copied().collect::<Vec<_>>())
+        .event_signature(
+            history
+                .token_data
+                .values()
+                .flat_map(|token_data| history.kind.events(token_data.standard))
+                .filter(|event| !update_events.contains(event))
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>(),
+        );
+
+    let res = match chain_state.provider.get_block_number().await {
+        Ok(to_block) => history::fetch_history(chain_state, filter, history.backfill, to_block)
+            .await
+            .map(|backfilled| (backfilled, to_block)),
+        Err(err) => Err(err),
+    };
+    let (backfilled, to_block) = match res {
+        Ok(res) => res,
+        Err(err) => {
+            debug!(?socket.id, subscription_id, %err, "Failed to fetch history");
+            let error_data = ErrorData {
+                id: socket.id,
+                subscription_id: Some(subscription_id),
+                message: "Failed to fetch history",
+                validation: None,
             };
-            // Convert the subscription into a stream
-            let mut stream = sub.into_stream();
-
-            let provider = Arc::clone(&chain_state.provider);
-            tokio::spawn(async move {
-                loop {
-                    tokio::select! {
-                        biased; // Check for task cancellation first
-
-                        _ = rx.changed() => {
-                            debug!(?socket.id, "Task cancelled");
-
-                            // Break the loop when the task is cancelled
-                            break;
-                        },
-                        Some(log) = stream.next() => {
-                            let event = match log.log_decode::<ERC721::Transfer>() {
-                                Ok(event) => event,
-                                Err(_) => continue, // Skip if errors occurs while decoding the event
-                            };
-                            let event_data = event.data();
-
-                            let token_data = match token_data.get(&event.address()) {
-                                Some(data) => data,
-                                None => unreachable!(),
-                            };
-
-                            // get token uri
-                            let token = ERC721::new(event.address(), Arc::clone(&provider));
-                            let token_uri = match token.tokenURI(event_data.tokenId).call().await {
-                                Ok(res) => res._0,
-                                Err(_) => continue,
-                            };
-
-                               let metadata_url = match token_uri.parse::<Url>() {
-                                    Ok(url) => url,
-                                    Err(_) =>  continue,
-                                };
-                            
-                            // sanitize metadata url
-                            let metadata = utils::extract_metadata_url(metadata_url);
-                            let (image_url, image_type) = match metadata {
-                                Some((url, MetadataType::Url)) => {
-                                    let res = match reqwest::get(url).await {
-                                        Ok(res) => res,
-                                        Err(_) => continue,
-                                    };
-                                    let metadata = match res.json::<Metadata>().await {
-                                        Ok(metadata) => metadata,
-                                        Err(_) => continue,
-                                    };
-                                    (Some(metadata.image), Some(MetadataType::Url))
-                                },
-                                Some((url, MetadataType::Data)) => (Some(url), Some(MetadataType::Data)),
-                                _ => (None, None),
-                            };
-
-                            let response_data = ResponseData {
-                                id: socket.id,
-                                address: event.address(),
-                                name: token_data.name.to_owned(),
-                                symbol: token_data.symbol.to_owned(),
-                                from: event_data.from,
-                                to: event_data.to,
-                                token_id: event_data.tokenId,
-                                image: image_url,
-                                image_type: image_type,
-                                block_number: log.block_number.unwrap_or_default(),
-                                transaction_hash: log.transaction_hash.unwrap_or_default(),
-                                timestamp: Utc::now()
-                            };
-                            socket.emit("response", &response_data).ok();
-                        },
-                        else => break, // Break the loop when the stream is closed
-                    }
-                }
-            });
-        },
-    );
+            socket.emit("error", &error_data).ok();
+
+            return None;
+        }
+    };
+
+    // Decode from the most recent log until enough transfers are found, a batch log holds several
+    let limit = history.backfill.limit();
+    let mut decoded = vec![];
+    let mut found = 0;
+    let mut dropped_block = None;
+    for log in backfilled.logs.iter().rev() {
+        if found >= limit {
+            dropped_block = log.block_number;
+            break;
+        }
+        let Some(token_data) = history.token_data.get(&log.address()) else {
+            continue;
+        };
+        let events = subscriptions::decode_log(chain_state, token_data, history.kind, log).await;
+        found += events.len();
+        decoded.push((log.block_number, events));
+    }
+
+    // Cut the oldest transfers of the oldest log over the limit
+    let excess = found.saturating_sub(limit);
+    if excess > 0 {
+        if let Some((block_number, events)) = decoded.last_mut() {
+            events.drain(..excess);
+            dropped_block = *block_number;
+        }
+    }
+    // The block of the last dropped transfer may still have transfers which are kept
+    let (from_block, truncated) = match dropped_block {
+        Some(block_number) => (block_number + 1, true),
+        None => (backfilled.from_block, backfilled.truncated),
+    };
+
+    let mut count = 0;
+    for event in decoded.into_iter().rev().flat_map(|(_, events)| events) {
+        let current = *heads.borrow();
+        emitter.event(event, true, &current);
+        count += 1;
+    }
+
+    let complete_data = BackfillCompleteData {
+        id: socket.id,
+        subscription_id,
+        count,
+        from_block,
+        to_block,
+        truncated,
+    };
+    socket.emit("backfill_complete", &complete_data).ok();
+
+    Some(to_block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_live_events_covered_by_the_history() {
+        // No history, every live event is sent
+        assert!(!is_replayed(None, 0));
+        assert!(!is_replayed(None, 100));
+        // Events up to the last replayed block were already sent
+        assert!(is_replayed(Some(100), 99));
+        assert!(is_replayed(Some(100), 100));
+        assert!(!is_replayed(Some(100), 101));
+    }
 }
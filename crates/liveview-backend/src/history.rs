@@ -0,0 +1,94 @@
+use alloy::{
+    rpc::types::{Filter, Log},
+    transports::TransportError,
+};
+use serde::Deserialize;
+
+use crate::{logs::LogPager, state::ChainState};
+
+/// Maximum number of historical transfers sent on subscribe
+const MAX_TRANSFERS: usize = 100;
+/// Maximum number of blocks searched for historical transfers
+const MAX_LOOKBACK: u64 = 200_000;
+
+/// Historical transfers requested on subscribe
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Backfill {
+    /// The last N transfers
+    Last(usize),
+    /// Every transfer since the given block, cut to the most recent `MAX_TRANSFERS`
+    /// and to the last `MAX_LOOKBACK` blocks
+    FromBlock(u64),
+}
+
+impl Backfill {
+    /// Maximum number of transfers sent for this backfill
+    pub(crate) fn limit(self) -> usize {
+        match self {
+            Backfill::Last(count) => count.min(MAX_TRANSFERS),
+            Backfill::FromBlock(_) => MAX_TRANSFERS,
+        }
+    }
+}
+
+/// Historical transfer logs of a backfill
+#[derive(Debug)]
+pub(crate) struct Backfilled {
+    /// Oldest first
+    pub(crate) logs: Vec<Log>,
+    /// First block whose logs are all part of `logs`
+    pub(crate) from_block: u64,
+    /// Whether older logs matching the backfill were left out
+    pub(crate) truncated: bool,
+}
+
+/// Fetch the historical transfer logs matching `filter` up to and including `to_block`, oldest first.
+///
+/// At most `backfill.limit()` logs are returned, the most recent ones are kept. A log can hold
+/// several transfers, so the decoded transfers may still have to be cut to the limit.
+pub(crate) async fn fetch_history(
+    chain_state: &ChainState,
+    filter: Filter,
+    backfill: Backfill,
+    to_block: u64,
+) -> Result<Backfilled, TransportError> {
+    let (count, requested_from) = match backfill {
+        Backfill::Last(count) => (count, 0),
+        Backfill::FromBlock(from_block) => (usize::MAX, from_block),
+    };
+    let limit = backfill.limit();
+    let lowest_block = requested_from.max(to_block.saturating_sub(MAX_LOOKBACK));
+    let mut pager = LogPager::default();
+
+    // Walk back from the head until enough logs are found, so that only the kept pages are loaded
+    let mut pages = vec![];
+    let mut found = 0;
+    let mut from = to_block.saturating_add(1);
+    while found < limit && from > lowest_block {
+        let (page, page_from) = pager
+            .prev(chain_state, &filter, lowest_block, from - 1)
+            .await?;
+        found += page.len();
+        pages.push(page);
+        from = page_from;
+    }
+
+    let mut logs = pages.into_iter().rev().flatten().collect::<Vec<_>>();
+    let dropped = logs.len().saturating_sub(limit);
+    // The block of the last dropped log may still have logs which are kept
+    let from_block = match dropped
+        .checked_sub(1)
+        .and_then(|last| logs[last].block_number)
+    {
+        Some(block_number) => block_number + 1,
+        None => from,
+    };
+    logs.drain(..dropped);
+
+    Ok(Backfilled {
+        truncated: logs.len() < count && from_block > requested_from,
+        logs,
+        from_block,
+    })
+}
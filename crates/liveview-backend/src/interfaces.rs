@@ -14,9 +14,257 @@ sol!(
     "abi/ERC721.json",
 );
 
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    ERC1155,
+    "abi/ERC1155.json",
+);
+
 sol!(
     #[allow(missing_docs)]
     #[sol(rpc)]
     Multicall,
     "abi/Multicall.json",
 );
+
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    interface Ownable {
+        function owner() external view returns (address);
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    interface ContractMetadata {
+        function contractURI() external view returns (string);
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    interface ERC2981 {
+        function royaltyInfo(uint256 tokenId, uint256 salePrice)
+            external
+            view
+            returns (address receiver, uint256 royaltyAmount);
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    interface Seaport {
+        struct SpentItem {
+            uint8 itemType;
+            address token;
+            uint256 identifier;
+            uint256 amount;
+        }
+
+        struct ReceivedItem {
+            uint8 itemType;
+            address token;
+            uint256 identifier;
+            uint256 amount;
+            address recipient;
+        }
+
+        event OrderFulfilled(
+            bytes32 orderHash,
+            address indexed offerer,
+            address indexed zone,
+            address recipient,
+            SpentItem[] offer,
+            ReceivedItem[] consideration
+        );
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    interface Blur {
+        struct Fee {
+            uint16 rate;
+            address recipient;
+        }
+
+        struct Order {
+            address trader;
+            uint8 side;
+            address matchingPolicy;
+            address collection;
+            uint256 tokenId;
+            uint256 amount;
+            address paymentToken;
+            uint256 price;
+            uint256 listingTime;
+            uint256 expirationTime;
+            Fee[] fees;
+            uint256 salt;
+            bytes extraParams;
+        }
+
+        event OrdersMatched(
+            address indexed maker,
+            address indexed taker,
+            Order sell,
+            bytes32 sellHash,
+            Order buy,
+            bytes32 buyHash
+        );
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    interface BlurV2 {
+        event Execution721Packed(
+            bytes32 orderHash,
+            uint256 tokenIdListingIndexTrader,
+            uint256 collectionPriceSide
+        );
+
+        event Execution721TakerFeePacked(
+            bytes32 orderHash,
+            uint256 tokenIdListingIndexTrader,
+            uint256 collectionPriceSide,
+            uint256 takerFeeRecipientRate
+        );
+
+        event Execution721MakerFeePacked(
+            bytes32 orderHash,
+            uint256 tokenIdListingIndexTrader,
+            uint256 collectionPriceSide,
+            uint256 makerFeeRecipientRate
+        );
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    interface LooksRare {
+        event TakerAsk(
+            bytes32 orderHash,
+            uint256 orderNonce,
+            address indexed taker,
+            address indexed maker,
+            address indexed strategy,
+            address currency,
+            address collection,
+            uint256 tokenId,
+            uint256 amount,
+            uint256 price
+        );
+
+        event TakerBid(
+            bytes32 orderHash,
+            uint256 orderNonce,
+            address indexed taker,
+            address indexed maker,
+            address indexed strategy,
+            address currency,
+            address collection,
+            uint256 tokenId,
+            uint256 amount,
+            uint256 price
+        );
+
+        event RoyaltyPayment(
+            address indexed collection,
+            uint256 indexed tokenId,
+            address indexed royaltyRecipient,
+            address currency,
+            uint256 amount
+        );
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    interface LooksRareV2 {
+        struct NonceInvalidationParameters {
+            bytes32 orderHash;
+            uint256 orderNonce;
+            bool isNonceInvalidated;
+        }
+
+        event TakerAsk(
+            NonceInvalidationParameters nonceInvalidationParameters,
+            address askUser,
+            address bidUser,
+            uint256 strategyId,
+            address currency,
+            address collection,
+            uint256[] itemIds,
+            uint256[] amounts,
+            address[2] feeRecipients,
+            uint256[3] feeAmounts
+        );
+
+        event TakerBid(
+            NonceInvalidationParameters nonceInvalidationParameters,
+            address bidUser,
+            address bidRecipient,
+            uint256 strategyId,
+            address currency,
+            address collection,
+            uint256[] itemIds,
+            uint256[] amounts,
+            address[2] feeRecipients,
+            uint256[3] feeAmounts
+        );
+    }
+);
+
+sol!(
+    #[allow(missing_docs)]
+    interface X2Y2 {
+        struct OrderItem {
+            uint256 price;
+            bytes data;
+        }
+
+        struct Fee {
+            uint256 percentage;
+            address to;
+        }
+
+        struct SettleDetail {
+            uint8 op;
+            uint256 orderIdx;
+            uint256 itemIdx;
+            uint256 price;
+            bytes32 itemHash;
+            address executionDelegate;
+            bytes dataReplacement;
+            uint256 bidIncentivePct;
+            uint256 aucMinIncrementPct;
+            uint256 aucIncDurationSecs;
+            Fee[] fees;
+        }
+
+        struct Pair {
+            address token;
+            uint256 tokenId;
+        }
+
+        event EvInventory(
+            bytes32 indexed itemHash,
+            address maker,
+            address taker,
+            uint256 orderSalt,
+            uint256 settleSalt,
+            uint256 intent,
+            uint256 delegateType,
+            uint256 deadline,
+            address currency,
+            bytes dataMask,
+            OrderItem item,
+            SettleDetail detail
+        );
+    }
+);
@@ -0,0 +1,503 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    providers::Provider,
+    rpc::types::{Filter, Log},
+    transports::{RpcError, TransportError, TransportErrorKind},
+};
+use futures_util::{stream, stream::BoxStream, StreamExt};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, info, warn};
+
+use crate::{blocks::Reorg, rpc::Endpoint, state::ChainState};
+
+/// Number of blocks requested by the first `eth_getLogs` call of a pager
+const INITIAL_LOG_RANGE: u64 = 1000;
+/// Maximum number of blocks requested by a single `eth_getLogs` call
+const MAX_LOG_RANGE: u64 = 10_000;
+/// Consecutive failed polls after which the stream is considered degraded
+const MAX_POLL_FAILURES: u32 = 3;
+/// Delay before the first reconnection attempt, doubled on every failed attempt
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound of the delay between reconnection attempts
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+/// How long to poll before retrying a failed subscription
+const SUBSCRIBE_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// How often a subscription checks whether a preferred endpoint is available again
+const FAILBACK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const CHANNEL_CAPACITY: usize = 256;
+
+/// How logs of a chain are received
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum LogSource {
+    /// `eth_subscribe` over a websocket endpoint
+    Subscribe,
+    /// `eth_getLogs` over new blocks on a fixed interval
+    Poll { interval: Duration },
+}
+
+/// Health of a log stream, reported to clients while it is degraded
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub(crate) enum StreamStatus {
+    /// The upstream failed and is being reopened
+    Reconnecting {
+        reason: String,
+        attempt: u32,
+        retry_in_ms: u64,
+    },
+    /// Logs missed while disconnected are being fetched
+    Backfilling { from_block: u64, to_block: u64 },
+    /// The stream recovered and is delivering new logs again
+    Live,
+}
+
+#[derive(Debug)]
+pub(crate) enum LogEvent {
+    /// A new log, or a log removed by a reorg when `removed` is set
+    Log(Log),
+    Status(StreamStatus),
+    /// The chain reorganized, logs after `fork_block` will be delivered again
+    Reorg(Reorg),
+}
+
+pub(crate) type LogStream = BoxStream<'static, LogEvent>;
+
+/// Stream the logs matching `filter` from new blocks using the chain's log source.
+///
+/// The upstream is reopened with backoff whenever it fails, and the blocks missed in
+/// the meantime are fetched with `eth_getLogs`, so no log is lost or delivered twice.
+pub(crate) async fn stream_logs(
+    chain_state: &ChainState,
+    filter: Filter,
+) -> Result<LogStream, TransportError> {
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+
+    let mut driver = Driver {
+        chain_state: chain_state.clone(),
+        filter,
+        tx,
+        reorgs: chain_state.blocks.subscribe_reorgs(),
+        covered_to: 0,
+        last: None,
+        pager: LogPager::default(),
+    };
+    let upstream = driver.open().await?;
+    driver.covered_to = chain_state.provider.get_block_number().await?;
+
+    tokio::spawn(driver.run(upstream));
+
+    Ok(stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        Some((event, rx))
+    })
+    .boxed())
+}
+
+enum Upstream {
+    Subscription {
+        stream: BoxStream<'static, Log>,
+        endpoint: Arc<Endpoint>,
+    },
+    Poll {
+        interval: Duration,
+        /// Set when polling stands in for a failed subscription
+        until: Option<Instant>,
+    },
+}
+
+struct Driver {
+    chain_state: ChainState,
+    filter: Filter,
+    tx: mpsc::Sender<LogEvent>,
+    reorgs: broadcast::Receiver<Reorg>,
+    /// Highest block whose logs were all delivered
+    covered_to: u64,
+    /// Block number and log index of the last delivered log
+    last: Option<(u64, u64)>,
+    pager: LogPager,
+}
+
+impl Driver {
+    async fn open(&self) -> Result<Upstream, TransportError> {
+        let interval = match self.chain_state.log_source {
+            LogSource::Poll { interval } => {
+                return Ok(Upstream::Poll {
+                    interval,
+                    until: None,
+                })
+            }
+            LogSource::Subscribe => self.chain_state.poll_interval,
+        };
+
+        match self
+            .chain_state
+            .endpoints
+            .subscribe_logs(&self.filter)
+            .await
+        {
+            Ok((sub, endpoint)) => Ok(Upstream::Subscription {
+                stream: sub.into_stream().boxed(),
+                endpoint,
+            }),
+            // Keep serving the chain over plain HTTP when no websocket endpoint works
+            Err(err) if self.chain_state.endpoints.iter().any(|e| !e.is_pubsub()) => {
+                warn!(chain = self.chain_state.name, %err, "Subscription failed, polling instead");
+                Ok(Upstream::Poll {
+                    interval,
+                    until: Some(Instant::now() + SUBSCRIBE_RETRY_INTERVAL),
+                })
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn run(mut self, mut upstream: Upstream) {
+        loop {
+            let res = match upstream {
+                Upstream::Subscription { stream, endpoint } => {
+                    self.forward(stream, &endpoint).await
+                }
+                Upstream::Poll { interval, until } => self.poll(interval, until).await,
+            };
+            if self.tx.is_closed() {
+                return;
+            }
+
+            upstream = match self.reconnect(res.err()).await {
+                Some(upstream) => upstream,
+                None => return,
+            };
+        }
+    }
+
+    /// Reopen the upstream and catch up on missed blocks.
+    /// `reason` is set when the previous upstream failed rather than being replaced.
+    async fn reconnect(&mut self, mut reason: Option<String>) -> Option<Upstream> {
+        let mut degraded = false;
+        let mut attempt = 0;
+
+        loop {
+            if let Some(reason) = reason.take() {
+                degraded = true;
+                let delay = RECONNECT_BASE_DELAY
+                    .saturating_mul(1 << attempt.min(16))
+                    .min(RECONNECT_MAX_DELAY);
+                attempt += 1;
+
+                warn!(
+                    chain = self.chain_state.name,
+                    reason,
+                    attempt,
+                    ?delay,
+                    "Log stream degraded"
+                );
+                self.send(LogEvent::Status(StreamStatus::Reconnecting {
+                    reason,
+                    attempt,
+                    retry_in_ms: delay.as_millis() as u64,
+                }))
+                .await
+                .ok()?;
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {},
+                    _ = self.tx.closed() => return None,
+                }
+            }
+
+            // Catch up before subscribing so that the subscription buffer does not overflow,
+            // then once more to close the gap between the two
+            let res = match self.backfill(degraded).await {
+                Ok(()) => match self.open().await {
+                    Ok(upstream) => self.backfill(degraded).await.map(|()| upstream),
+                    Err(err) => Err(err),
+                },
+                Err(err) => Err(err),
+            };
+
+            match res {
+                Ok(upstream) => {
+                    if degraded {
+                        info!(chain = self.chain_state.name, "Log stream recovered");
+                        self.send(LogEvent::Status(StreamStatus::Live)).await.ok()?;
+                    }
+
+                    return Some(upstream);
+                }
+                Err(err) => reason = Some(err.to_string()),
+            }
+        }
+    }
+
+    /// Forward logs from a subscription until it closes or a preferred endpoint recovers
+    async fn forward(
+        &mut self,
+        mut stream: BoxStream<'static, Log>,
+        endpoint: &Endpoint,
+    ) -> Result<(), String> {
+        let mut failback = tokio::time::interval(FAILBACK_CHECK_INTERVAL);
+        failback.tick().await;
+
+        loop {
+            tokio::select! {
+                log = stream.next() => {
+                    let Some(log) = log else {
+                        endpoint.mark_failure(&TransportErrorKind::backend_gone()).await;
+                        return Err("Subscription closed".to_owned());
+                    };
+
+                    // Logs arrive in order, so every earlier block is complete
+                    if let (Some(block_number), false) = (log.block_number, log.removed) {
+                        self.covered_to = self.covered_to.max(block_number.saturating_sub(1));
+                    }
+                    if self.send_log(log).await.is_err() {
+                        return Ok(());
+                    }
+                },
+                Ok(reorg) = self.reorgs.recv() => {
+                    if self.rewind(reorg).await.is_err() {
+                        return Ok(());
+                    }
+                    // The provider may not resend the logs of the new branch
+                    if let Err(err) = self.backfill(false).await {
+                        return Err(err.to_string());
+                    }
+                },
+                _ = failback.tick() => {
+                    let preferred = self
+                        .chain_state
+                        .endpoints
+                        .candidates()
+                        .into_iter()
+                        .find(|e| e.is_pubsub() && e.is_healthy());
+                    if preferred.is_some_and(|e| e.priority < endpoint.priority) {
+                        debug!(chain = self.chain_state.name, "Failing back to preferred endpoint");
+                        return Ok(());
+                    }
+                },
+                _ = self.tx.closed() => return Ok(()),
+            }
+        }
+    }
+
+    /// Poll for logs of new blocks, until `until` when polling replaces a subscription
+    async fn poll(&mut self, interval: Duration, until: Option<Instant>) -> Result<(), String> {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut failures = 0;
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {},
+                Ok(reorg) = self.reorgs.recv() => {
+                    // The next poll fetches the logs of the new branch
+                    if self.rewind(reorg).await.is_err() {
+                        return Ok(());
+                    }
+                    continue;
+                },
+                _ = self.tx.closed() => return Ok(()),
+            }
+            if until.is_some_and(|until| Instant::now() >= until) {
+                return Ok(());
+            }
+
+            match self.backfill(false).await {
+                Ok(()) => failures = 0,
+                Err(err) => {
+                    warn!(chain = self.chain_state.name, %err, "Failed to poll logs");
+
+                    failures += 1;
+                    if failures >= MAX_POLL_FAILURES {
+                        return Err(err.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Deliver the logs of all blocks after `covered_to` up to the current head
+    async fn backfill(&mut self, report: bool) -> Result<(), TransportError> {
+        let head = self.chain_state.provider.get_block_number().await?;
+        if head <= self.covered_to {
+            return Ok(());
+        }
+
+        if report {
+            self.send(LogEvent::Status(StreamStatus::Backfilling {
+                from_block: self.covered_to + 1,
+                to_block: head,
+            }))
+            .await
+            .ok();
+        }
+
+        while self.covered_to < head {
+            let (logs, to_block) = self
+                .pager
+                .next(&self.chain_state, &self.filter, self.covered_to + 1, head)
+                .await?;
+
+            for log in logs {
+                if self.send_log(log).await.is_err() {
+                    return Ok(());
+                }
+            }
+            self.covered_to = to_block;
+        }
+
+        Ok(())
+    }
+
+    /// Forget everything delivered after the fork so that the new branch is delivered
+    async fn rewind(&mut self, reorg: Reorg) -> Result<(), ()> {
+        debug!(
+            chain = self.chain_state.name,
+            fork_block = reorg.fork_block,
+            depth = reorg.depth,
+            "Rewinding log stream"
+        );
+        self.rewind_to(reorg.fork_block);
+        self.send(LogEvent::Reorg(reorg)).await
+    }
+
+    fn rewind_to(&mut self, block_number: u64) {
+        self.covered_to = self.covered_to.min(block_number);
+        self.last = self.last.map(|last| last.min((block_number, u64::MAX)));
+    }
+
+    /// Deliver a log unless it was already delivered
+    async fn send_log(&mut self, log: Log) -> Result<(), ()> {
+        // Removed logs are always delivered, and their block may be delivered again
+        if log.removed {
+            if let Some(block_number) = log.block_number {
+                self.rewind_to(block_number.saturating_sub(1));
+            }
+            return self.send(LogEvent::Log(log)).await;
+        }
+
+        if let (Some(block_number), Some(log_index)) = (log.block_number, log.log_index) {
+            if self
+                .last
+                .is_some_and(|last| (block_number, log_index) <= last)
+            {
+                return Ok(());
+            }
+            self.last = Some((block_number, log_index));
+        }
+
+        self.send(LogEvent::Log(log)).await
+    }
+
+    async fn send(&self, event: LogEvent) -> Result<(), ()> {
+        self.tx.send(event).await.map_err(|_| ())
+    }
+}
+
+/// Fetches logs over block ranges with `eth_getLogs`, shrinking the range whenever
+/// the provider rejects it as too large and growing it back on success
+#[derive(Debug)]
+pub(crate) struct LogPager {
+    range: u64,
+}
+
+impl Default for LogPager {
+    fn default() -> Self {
+        Self {
+            range: INITIAL_LOG_RANGE,
+        }
+    }
+}
+
+impl LogPager {
+    /// Fetch the logs of the first page of `from_block..=to_block`,
+    /// returning them with the last block of the page
+    pub(crate) async fn next(
+        &mut self,
+        chain_state: &ChainState,
+        filter: &Filter,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<(Vec<Log>, u64), TransportError> {
+        loop {
+            let page_to = to_block.min(from_block.saturating_add(self.range - 1));
+            match self.fetch(chain_state, filter, from_block, page_to).await {
+                Ok(Some(logs)) => return Ok((logs, page_to)),
+                Ok(None) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Fetch the logs of the last page of `from_block..=to_block`,
+    /// returning them with the first block of the page
+    pub(crate) async fn prev(
+        &mut self,
+        chain_state: &ChainState,
+        filter: &Filter,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<(Vec<Log>, u64), TransportError> {
+        loop {
+            let page_from = from_block.max(to_block.saturating_sub(self.range - 1));
+            match self.fetch(chain_state, filter, page_from, to_block).await {
+                Ok(Some(logs)) => return Ok((logs, page_from)),
+                Ok(None) => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns `None` when the range was too large and has been shrunk
+    async fn fetch(
+        &mut self,
+        chain_state: &ChainState,
+        filter: &Filter,
+        from_block: u64,
+        to_block: u64,
+    ) -> Result<Option<Vec<Log>>, TransportError> {
+        let filter = filter.clone().from_block(from_block).to_block(to_block);
+
+        match chain_state.provider.get_logs(&filter).await {
+            Ok(logs) => {
+                debug!(
+                    chain = chain_state.name,
+                    from_block,
+                    to_block,
+                    count = logs.len(),
+                    "Fetched logs"
+                );
+                self.range = (self.range * 2).min(MAX_LOG_RANGE);
+
+                Ok(Some(logs))
+            }
+            Err(err) if to_block > from_block && is_range_error(&err) => {
+                self.range = (to_block - from_block).div_ceil(2);
+                debug!(chain = chain_state.name, %err, range = self.range, "Shrinking log range");
+
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Whether the provider refused an `eth_getLogs` call because of its block range or result size
+fn is_range_error(err: &TransportError) -> bool {
+    let RpcError::ErrorResp(payload) = err else {
+        return false;
+    };
+    let message = payload.message.to_lowercase();
+    if message.contains("rate limit") {
+        return false;
+    }
+
+    ["range", "too many", "too large", "exceed", "limit"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
@@ -1,6 +1,10 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use alloy::providers::ProviderBuilder;
+use alloy::{
+    providers::{Provider, ProviderBuilder},
+    rpc::{client::RpcClient, types::BlockTransactionsKind},
+    transports::Transport,
+};
 use clap::Parser;
 use eyre::Context;
 use socketioxide::SocketIo;
@@ -13,16 +17,38 @@ use tracing::level_filters::LevelFilter;
 use tracing_subscriber::EnvFilter;
 
 mod args;
+mod blocks;
+mod cache;
 mod data;
+mod fetcher;
 mod handlers;
+mod history;
 mod interfaces;
+mod logs;
+mod metadata;
+mod positions;
+mod resolver;
 mod routes;
+mod rpc;
+mod sales;
 mod state;
+mod store;
+mod subscriptions;
+mod tokens;
 mod utils;
 
 use args::Args;
-use data::Data;
+use blocks::BlockTracker;
+use cache::Caches;
+use data::{Data, LogSourceMode};
+use logs::LogSource;
+use resolver::Resolver;
+use rpc::{Endpoints, FailoverTransport};
 use state::{AppState, ChainState};
+use store::Store;
+
+/// How often failing RPC endpoints are probed for recovery
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
@@ -40,57 +66,142 @@ async fn main() -> eyre::Result<()> {
     )
     .context("Failed to parse data file")?;
 
+    let resolver = Arc::new(
+        Resolver::new(data.gateways, &data.fetcher)
+            .context("Failed to create metadata HTTP client")?,
+    );
+    let caches = Arc::new(Caches::new(&data.cache));
+
+    // Warm the caches from the previous runs
+    let store = match args.store_path {
+        Some(store_path) => {
+            let config = data.cache.clone();
+            let caches = Arc::clone(&caches);
+            let (store, collections, tokens) = tokio::task::spawn_blocking(move || {
+                let store = Store::open(&store_path, &config).context("Failed to open store")?;
+                let (collections, tokens) = store.warm(&caches);
+                eyre::Ok((store, collections, tokens))
+            })
+            .await??;
+            tracing::info!(collections, tokens, "Caches warmed from store");
+            Some(Arc::new(store))
+        }
+        None => None,
+    };
+
+    // Create a new state for each configured chain
+    let mut chains = Vec::with_capacity(data.chains.len());
+    for chain in data.chains {
+        if chains
+            .iter()
+            .any(|c: &ChainState| c.name == chain.name || c.chain_id == chain.chain_id)
+        {
+            eyre::bail!(
+                "Duplicate chain in data file: {} ({})",
+                chain.name,
+                chain.chain_id
+            );
+        }
+
+        chain.validate()?;
+
+        let endpoints = Arc::new(Endpoints::new(
+            chain
+                .rpc_urls
+                .into_iter()
+                .map(|rpc| (rpc.url, rpc.priority)),
+        ));
+        endpoints.spawn_health_check(HEALTH_CHECK_INTERVAL);
+
+        let has_pubsub = endpoints.iter().any(|endpoint| endpoint.is_pubsub());
+        let poll_interval = Duration::from_millis(chain.poll_interval_ms);
+        let log_source = match chain.log_source {
+            Some(LogSourceMode::Subscribe) if !has_pubsub => {
+                eyre::bail!("Subscribing on {} requires a ws:// RPC URL", chain.name);
+            }
+            Some(LogSourceMode::Subscribe) => LogSource::Subscribe,
+            Some(LogSourceMode::Poll) => LogSource::Poll {
+                interval: poll_interval,
+            },
+            None if has_pubsub => LogSource::Subscribe,
+            None => LogSource::Poll {
+                interval: poll_interval,
+            },
+        };
+
+        let provider = ProviderBuilder::new().on_client(RpcClient::new(
+            FailoverTransport::new(Arc::clone(&endpoints)).boxed(),
+            false,
+        ));
+
+        // Make sure the RPC actually serves the configured chain
+        match provider.get_chain_id().await {
+            Ok(chain_id) if chain_id != chain.chain_id => {
+                eyre::bail!(
+                    "RPC for {} reports chain id {} instead of {}",
+                    chain.name,
+                    chain_id,
+                    chain.chain_id
+                );
+            }
+            Ok(_) => {}
+            Err(err) => {
+                tracing::warn!(chain = chain.name, ?err, "Failed to verify chain id");
+            }
+        }
+
+        // Subscriptions waiting for a head the chain doesn't report would never be confirmed
+        if let Some(tag) = chain.confirmation.head_tag() {
+            match provider
+                .get_block_by_number(tag, BlockTransactionsKind::Hashes)
+                .await
+            {
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    eyre::bail!("RPC for {} doesn't report the {} block", chain.name, tag);
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        chain = chain.name,
+                        %tag,
+                        ?err,
+                        "Failed to fetch the block the confirmation waits for, \
+                         subscriptions will be rejected until the chain reports it"
+                    );
+                }
+            }
+        }
+
+        let provider = Arc::new(provider);
+        let blocks = Arc::new(BlockTracker::new(chain.name.to_owned()));
+        blocks.spawn(Arc::clone(&provider), poll_interval);
+
+        tracing::info!(
+            chain = chain.name,
+            chain_id = chain.chain_id,
+            "Chain configured"
+        );
+        chains.push(ChainState {
+            name: chain.name,
+            chain_id: chain.chain_id,
+            multicall_address: chain.multicall_address,
+            provider,
+            endpoints,
+            log_source,
+            poll_interval,
+            subscriptions: Arc::default(),
+            resolver: Arc::clone(&resolver),
+            caches: Arc::clone(&caches),
+            store: store.clone(),
+            receipts: Arc::default(),
+            pending_validations: Arc::default(),
+            blocks,
+            confirmation: chain.confirmation,
+        });
+    }
+
     // Create a new state for the application
-    let app_state = Arc::new(AppState {
-        mainnet: ChainState {
-            multicall_address: data.mainnet.multicall_address,
-            provider: Arc::new(
-                ProviderBuilder::new()
-                    .on_builtin(data.mainnet.rpc_url.as_str())
-                    .await?,
-            ),
-        },
-        base: ChainState {
-            multicall_address: data.base.multicall_address,
-            provider: Arc::new(
-                ProviderBuilder::new()
-                    .on_builtin(data.base.rpc_url.as_str())
-                    .await?,
-            ),
-        },
-        arbitrum: ChainState {
-            multicall_address: data.arbitrum.multicall_address,
-            provider: Arc::new(
-                ProviderBuilder::new()
-                    .on_builtin(data.arbitrum.rpc_url.as_str())
-                    .await?,
-            ),
-        },
-        optimism: ChainState {
-            multicall_address: data.optimism.multicall_address,
-            provider: Arc::new(
-                ProviderBuilder::new()
-                    .on_builtin(data.optimism.rpc_url.as_str())
-                    .await?,
-            ),
-        },
-        polygon: ChainState {
-            multicall_address: data.polygon.multicall_address,
-            provider: Arc::new(
-                ProviderBuilder::new()
-                    .on_builtin(data.polygon.rpc_url.as_str())
-                    .await?,
-            ),
-        },
-        bsc: ChainState {
-            multicall_address: data.bsc.multicall_address,
-            provider: Arc::new(
-                ProviderBuilder::new()
-                    .on_builtin(data.bsc.rpc_url.as_str())
-                    .await?,
-            ),
-        },
-    });
+    let app_state = Arc::new(AppState::new(chains, args.max_subscriptions, caches));
 
     // Create a new Socket.IO layer
     let (socket_layer, socket_io) = SocketIo::builder()
@@ -106,6 +217,12 @@ async fn main() -> eyre::Result<()> {
 
     let app = axum::Router::new()
         .route("/api/search", axum::routing::get(routes::search::search))
+        .route(
+            "/api/search/batch",
+            axum::routing::post(routes::batch_search::batch_search),
+        )
+        .route("/api/token", axum::routing::get(routes::token::token))
+        .route("/api/cache", axum::routing::get(routes::cache::cache))
         .layer(socket_layer)
         .with_state(Arc::clone(&app_state))
         .layer(cors_layer)
@@ -0,0 +1,516 @@
+use std::{ops::RangeInclusive, sync::Arc};
+
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::RootProvider,
+    rpc::types::Log,
+    sol_types::SolEvent,
+    transports::BoxTransport,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::debug;
+
+use crate::{
+    fetcher::FetchError,
+    interfaces::{ERC1155, ERC721},
+    resolver::{self, Resolver},
+    state::ChainState,
+    tokens::TokenStandard,
+    utils::{self, MetadataType},
+};
+
+/// Token metadata normalized from the common shapes found in the wild
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct TokenMetadata {
+    pub(crate) name: Option<String>,
+    pub(crate) description: Option<String>,
+    pub(crate) image: Option<String>,
+    pub(crate) animation_url: Option<String>,
+    pub(crate) external_url: Option<String>,
+    pub(crate) background_color: Option<String>,
+    pub(crate) attributes: Vec<Attribute>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Attribute {
+    /// `None` for attributes listed as bare values
+    pub(crate) trait_type: Option<String>,
+    pub(crate) value: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) display_type: Option<String>,
+}
+
+/// Metadata of a token resolved from its token URI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ResolvedMetadata {
+    /// Metadata JSON as served, `None` when the token URI is not JSON
+    pub(crate) raw: Option<Value>,
+    pub(crate) metadata: Option<TokenMetadata>,
+    pub(crate) image: Option<String>,
+    /// Whether the image is a link or inline data
+    pub(crate) image_type: Option<MetadataType>,
+    /// Media type of inline images, e.g. `image/svg+xml`
+    pub(crate) image_media_type: Option<String>,
+}
+
+/// Token URI of a token and the metadata it points to
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct TokenInfo {
+    pub(crate) token_uri: String,
+    pub(crate) resolved: ResolvedMetadata,
+}
+
+impl TokenInfo {
+    /// Approximate size in memory, dominated by the metadata JSON and inline images
+    pub(crate) fn weight(&self) -> usize {
+        let resolved = serde_json::to_vec(&self.resolved).map_or(0, |json| json.len());
+        self.token_uri.len() + resolved
+    }
+}
+
+/// Resolve the metadata of a token through its `tokenURI`, or `uri` for ERC1155.
+///
+/// `refresh` bypasses the cached entry, as in [`lookup`].
+/// Fails when the token URI or its metadata cannot be fetched.
+pub(crate) async fn fetch_metadata(
+    chain_state: &ChainState,
+    standard: TokenStandard,
+    address: Address,
+    token_id: U256,
+    refresh: bool,
+) -> Result<ResolvedMetadata, FetchError> {
+    // Fungible tokens have no per token metadata
+    if standard == TokenStandard::Erc20 {
+        return Ok(ResolvedMetadata {
+            raw: None,
+            metadata: None,
+            image: None,
+            image_type: None,
+            image_media_type: None,
+        });
+    }
+
+    let info = match lookup(chain_state, standard, address, token_id, refresh).await {
+        Ok(info) => info,
+        Err(err) => {
+            debug!(%address, %token_id, %err, "Failed to resolve metadata");
+            return Err(err);
+        }
+    };
+    Ok(info.resolved.clone())
+}
+
+/// Token URI and metadata of a token, cached per chain, contract and token id.
+///
+/// `refresh` replaces the cached entry and asks gateways and CDNs for a fresh copy.
+pub(crate) async fn lookup(
+    chain_state: &ChainState,
+    standard: TokenStandard,
+    address: Address,
+    token_id: U256,
+    refresh: bool,
+) -> Result<Arc<TokenInfo>, FetchError> {
+    let key = (chain_state.chain_id, address, token_id);
+    if refresh {
+        chain_state.caches.tokens.invalidate(&key);
+    }
+
+    chain_state
+        .caches
+        .tokens
+        .get_or_try_init(
+            key,
+            || async {
+                let token_uri = fetch_token_uri(&chain_state.provider, standard, address, token_id)
+                    .await
+                    .ok_or(FetchError::MissingTokenUri)?;
+                let resolved = resolve_metadata(
+                    &chain_state.resolver,
+                    standard,
+                    &token_uri,
+                    token_id,
+                    refresh,
+                )
+                .await?;
+
+                Ok(Arc::new(TokenInfo {
+                    token_uri,
+                    resolved,
+                }))
+            },
+            // Metadata invalidated while it was fetched is stale and must not be stored
+            |info| {
+                if let Some(store) = &chain_state.store {
+                    store.put_token(chain_state.chain_id, address, token_id, info);
+                }
+            },
+        )
+        .await
+}
+
+/// Signatures of the ERC4906 events announcing metadata changes
+pub(crate) fn update_events() -> [B256; 2] {
+    [
+        ERC721::MetadataUpdate::SIGNATURE_HASH,
+        ERC721::BatchMetadataUpdate::SIGNATURE_HASH,
+    ]
+}
+
+/// Token ids whose metadata an ERC4906 event announces as changed
+pub(crate) fn updated_tokens(log: &Log) -> Option<RangeInclusive<U256>> {
+    match *log.topic0()? {
+        ERC721::MetadataUpdate::SIGNATURE_HASH => {
+            let event = log.log_decode::<ERC721::MetadataUpdate>().ok()?;
+            let token_id = event.data()._tokenId;
+            Some(token_id..=token_id)
+        }
+        ERC721::BatchMetadataUpdate::SIGNATURE_HASH => {
+            let event = log.log_decode::<ERC721::BatchMetadataUpdate>().ok()?;
+            Some(event.data()._fromTokenId..=event.data()._toTokenId)
+        }
+        _ => None,
+    }
+}
+
+/// Drop the cached metadata of the tokens `token_ids` of `address`
+pub(crate) fn invalidate(
+    chain_state: &ChainState,
+    address: Address,
+    token_ids: &RangeInclusive<U256>,
+) {
+    let chain_id = chain_state.chain_id;
+    // The cache goes first: a lookup which stored its metadata before is removed from the store,
+    // one which finishes after finds its entry invalidated and doesn't store it
+    if token_ids.start() == token_ids.end() {
+        chain_state
+            .caches
+            .tokens
+            .invalidate(&(chain_id, address, *token_ids.start()));
+    } else {
+        chain_state.caches.tokens.invalidate_matching(
+            |(cached_chain_id, cached_address, token_id)| {
+                *cached_chain_id == chain_id
+                    && *cached_address == address
+                    && token_ids.contains(token_id)
+            },
+        );
+    }
+
+    if let Some(store) = &chain_state.store {
+        store.remove_tokens(chain_id, address, token_ids);
+    }
+}
+
+/// Fetch the token URI through `tokenURI`, or `uri` for ERC1155
+pub(crate) async fn fetch_token_uri(
+    provider: &Arc<RootProvider<BoxTransport>>,
+    standard: TokenStandard,
+    address: Address,
+    token_id: U256,
+) -> Option<String> {
+    match standard {
+        TokenStandard::Erc20 => None,
+        TokenStandard::Erc721 => {
+            let token = ERC721::new(address, Arc::clone(provider));
+            Some(token.tokenURI(token_id).call().await.ok()?._0)
+        }
+        TokenStandard::Erc1155 => {
+            let token = ERC1155::new(address, Arc::clone(provider));
+            Some(substitute_id(
+                &token.uri(token_id).call().await.ok()?._0,
+                token_id,
+            ))
+        }
+    }
+}
+
+/// Resolve the metadata and image a token URI points to.
+///
+/// `refresh` asks gateways and CDNs in between for a fresh copy.
+pub(crate) async fn resolve_metadata(
+    resolver: &Resolver,
+    standard: TokenStandard,
+    token_uri: &str,
+    token_id: U256,
+    refresh: bool,
+) -> Result<ResolvedMetadata, FetchError> {
+    let raw = if utils::is_data_uri(token_uri) {
+        let data_uri = utils::decode_data_uri(token_uri).ok_or(FetchError::InvalidDataUri)?;
+        // Some on-chain collections point straight at their image
+        if data_uri.media_type.starts_with("image/") {
+            return Ok(ResolvedMetadata {
+                raw: None,
+                metadata: None,
+                image: Some(token_uri.to_owned()),
+                image_type: Some(MetadataType::Data),
+                image_media_type: Some(data_uri.media_type),
+            });
+        }
+        serde_json::from_slice::<Value>(&data_uri.data).map_err(FetchError::InvalidJson)?
+    } else if resolver::locate(token_uri).is_some() {
+        resolver.fetch_json(token_uri, refresh).await?
+    } else {
+        // Unsupported schemes have no metadata
+        return Ok(ResolvedMetadata {
+            raw: None,
+            metadata: None,
+            image: None,
+            image_type: None,
+            image_media_type: None,
+        });
+    };
+
+    let mut metadata = normalize(&raw);
+    if standard == TokenStandard::Erc1155 {
+        metadata.image = metadata.image.map(|image| substitute_id(&image, token_id));
+    }
+    // Clients can't load `ipfs://` and similar links themselves
+    metadata.image = metadata.image.and_then(|image| resolver.public_url(&image));
+    metadata.animation_url = metadata
+        .animation_url
+        .and_then(|animation_url| resolver.public_url(&animation_url));
+
+    let (image_type, image_media_type) = match &metadata.image {
+        Some(image) if utils::is_data_uri(image) => {
+            (Some(MetadataType::Data), utils::data_uri_media_type(image))
+        }
+        Some(_) => (Some(MetadataType::Url), None),
+        None => (None, None),
+    };
+
+    Ok(ResolvedMetadata {
+        raw: Some(raw),
+        image: metadata.image.clone(),
+        metadata: Some(metadata),
+        image_type,
+        image_media_type,
+    })
+}
+
+/// Normalize metadata JSON, accepting `image_url` for `image`, `properties` for `attributes`
+/// and numbers where strings are expected
+pub(crate) fn normalize(raw: &Value) -> TokenMetadata {
+    let field = |keys: &[&str]| keys.iter().find_map(|key| raw.get(key).and_then(text));
+
+    let attributes = match raw.get("attributes").or_else(|| raw.get("traits")) {
+        Some(attributes) => normalize_attributes(attributes),
+        None => raw
+            .get("properties")
+            .map(normalize_attributes)
+            .unwrap_or_default(),
+    };
+
+    TokenMetadata {
+        name: field(&["name", "title"]),
+        description: field(&["description"]),
+        image: field(&["image", "image_url", "imageUrl"]),
+        animation_url: field(&["animation_url", "animationUrl"]),
+        external_url: field(&["external_url", "externalUrl"]),
+        background_color: field(&["background_color", "backgroundColor"]),
+        attributes,
+    }
+}
+
+/// Attributes are either a list of `{ trait_type, value }` or an object of trait types to values
+fn normalize_attributes(attributes: &Value) -> Vec<Attribute> {
+    match attributes {
+        Value::Array(attributes) => attributes.iter().filter_map(list_attribute).collect(),
+        Value::Object(attributes) => attributes
+            .iter()
+            .filter_map(|(trait_type, value)| keyed_attribute(trait_type, value))
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn list_attribute(attribute: &Value) -> Option<Attribute> {
+    let attribute = match attribute {
+        Value::Object(attribute) => attribute,
+        // Bare values without a trait type
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => {
+            return Some(Attribute {
+                trait_type: None,
+                value: attribute.to_owned(),
+                display_type: None,
+            });
+        }
+        _ => return None,
+    };
+
+    let trait_type = ["trait_type", "traitType", "key", "name"]
+        .iter()
+        .find_map(|key| attribute.get(*key).and_then(text));
+    let display_type = attribute.get("display_type").and_then(text);
+    let value = attribute_value(attribute, display_type.as_deref())?;
+
+    Some(Attribute {
+        trait_type,
+        value,
+        display_type,
+    })
+}
+
+/// Entries of `properties` either hold the value directly or `{ value }`,
+/// non-scalar entries like file lists are skipped
+fn keyed_attribute(trait_type: &str, value: &Value) -> Option<Attribute> {
+    let (value, display_type) = match value {
+        Value::Object(value) => {
+            let display_type = value.get("display_type").and_then(text);
+            (
+                attribute_value(value, display_type.as_deref())?,
+                display_type,
+            )
+        }
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => (value.to_owned(), None),
+        _ => return None,
+    };
+
+    Some(Attribute {
+        trait_type: Some(trait_type.to_owned()),
+        value,
+        display_type,
+    })
+}
+
+/// Numeric display types get string-encoded numbers converted to numbers
+fn attribute_value(attribute: &Map<String, Value>, display_type: Option<&str>) -> Option<Value> {
+    let value = attribute.get("value")?;
+
+    let is_numeric = display_type.is_some_and(|display_type| {
+        matches!(
+            display_type,
+            "number" | "boost_number" | "boost_percentage" | "date"
+        )
+    });
+    match value {
+        Value::String(number) if is_numeric => match number.trim().parse::<serde_json::Number>() {
+            Ok(number) => Some(Value::Number(number)),
+            Err(_) => Some(value.to_owned()),
+        },
+        Value::String(_) | Value::Number(_) | Value::Bool(_) => Some(value.to_owned()),
+        _ => None,
+    }
+}
+
+/// Non-empty strings, with numbers accepted as their decimal representation
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) if !text.trim().is_empty() => Some(text.to_owned()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+/// Replace the `{id}` placeholder of ERC1155 URIs with the token id as 64 lowercase hex characters
+fn substitute_id(uri: &str, token_id: U256) -> String {
+    uri.replace("{id}", &format!("{:064x}", token_id))
+}
+
+/// Fetch the collection metadata found at a `contractURI`
+pub(crate) async fn fetch_contract_metadata(
+    resolver: &Resolver,
+    contract_uri: &str,
+) -> Option<Value> {
+    if utils::is_data_uri(contract_uri) {
+        let data_uri = utils::decode_data_uri(contract_uri)?;
+        return serde_json::from_slice(&data_uri.data).ok();
+    }
+
+    resolver.fetch_json(contract_uri, false).await.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn substitutes_padded_hex_ids() {
+        for (uri, token_id, expected) in [
+            (
+                "ipfs://Qm/{id}.json",
+                U256::from(1),
+                "ipfs://Qm/0000000000000000000000000000000000000000000000000000000000000001.json",
+            ),
+            (
+                "https://example.com/{id}",
+                U256::from(0x4cce0_u64),
+                "https://example.com/000000000000000000000000000000000000000000000000000000000004cce0",
+            ),
+            (
+                "https://example.com/{id}",
+                U256::MAX,
+                "https://example.com/ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            ),
+            // Only the lowercase placeholder is defined by ERC-1155
+            ("https://example.com/{ID}/1", U256::from(1), "https://example.com/{ID}/1"),
+        ] {
+            assert_eq!(substitute_id(uri, token_id), expected);
+        }
+    }
+
+    #[test]
+    fn normalizes_field_aliases() {
+        for (raw, expected) in [
+            (json!({ "image": "ipfs://a" }), Some("ipfs://a")),
+            (json!({ "image_url": "ipfs://b" }), Some("ipfs://b")),
+            (json!({ "imageUrl": "ipfs://c" }), Some("ipfs://c")),
+            (
+                json!({ "image": "", "image_url": "ipfs://d" }),
+                Some("ipfs://d"),
+            ),
+            (json!({ "image": "  " }), None),
+            (json!({ "image": null }), None),
+        ] {
+            assert_eq!(normalize(&raw).image.as_deref(), expected, "{raw}");
+        }
+
+        let metadata = normalize(&json!({ "title": 42, "backgroundColor": "ffffff" }));
+        assert_eq!(metadata.name.as_deref(), Some("42"));
+        assert_eq!(metadata.background_color.as_deref(), Some("ffffff"));
+    }
+
+    #[test]
+    fn normalizes_attributes() {
+        for (raw, expected) in [
+            (
+                json!({ "attributes": [{ "trait_type": "Eyes", "value": "Blue" }] }),
+                vec![(Some("Eyes"), json!("Blue"), None)],
+            ),
+            (
+                json!({ "attributes": [{ "display_type": "number", "trait_type": "Level", "value": " 5 " }] }),
+                vec![(Some("Level"), json!(5), Some("number"))],
+            ),
+            (
+                json!({ "traits": [{ "traitType": "Hat", "value": true }, "Rare", { "value": [] }] }),
+                vec![
+                    (Some("Hat"), json!(true), None),
+                    (None, json!("Rare"), None),
+                ],
+            ),
+            (
+                json!({ "properties": { "Size": 3, "Files": [], "Color": { "value": "Red" } } }),
+                vec![
+                    (Some("Color"), json!("Red"), None),
+                    (Some("Size"), json!(3), None),
+                ],
+            ),
+            (json!({ "attributes": "none" }), vec![]),
+        ] {
+            let attributes = normalize(&raw).attributes;
+            let attributes = attributes
+                .iter()
+                .map(|attribute| {
+                    (
+                        attribute.trait_type.as_deref(),
+                        attribute.value.clone(),
+                        attribute.display_type.as_deref(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(attributes, expected, "{raw}");
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, FixedBytes, B256, U256},
+    rpc::types::Log,
+    sol_types::SolEvent,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{interfaces::ERC721, state::ChainState, subscriptions};
+
+/// Liquidity position of a position manager NFT, as returned by `positions(tokenId)`
+#[derive(Debug, Serialize)]
+pub(crate) struct Position {
+    pub(crate) token0: Address,
+    pub(crate) token1: Address,
+    /// Tick spacing of the pool, the `tickSpacing` output of the bundled ABI.
+    /// Uniswap V3 position managers return their `uint24` fee tier in this slot instead.
+    pub(crate) tick_spacing: i32,
+    pub(crate) tick_lower: i32,
+    pub(crate) tick_upper: i32,
+    pub(crate) liquidity: u128,
+    pub(crate) tokens_owed0: u128,
+    pub(crate) tokens_owed1: u128,
+}
+
+/// Change of a position reported by the position manager
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub(crate) enum PositionChange {
+    IncreaseLiquidity {
+        liquidity: u128,
+        amount0: U256,
+        amount1: U256,
+    },
+    DecreaseLiquidity {
+        liquidity: u128,
+        amount0: U256,
+        amount1: U256,
+    },
+    Collect {
+        recipient: Address,
+        amount0: U256,
+        amount1: U256,
+    },
+}
+
+/// A decoded position change enriched with the position it applies to
+#[derive(Debug, Serialize)]
+pub(crate) struct PositionEvent {
+    pub(crate) address: Address,
+    pub(crate) token_id: U256,
+    #[serde(flatten)]
+    pub(crate) change: PositionChange,
+    /// `None` when the position could not be fetched
+    pub(crate) position: Option<Position>,
+    pub(crate) block_number: u64,
+    pub(crate) block_hash: FixedBytes<32>,
+    pub(crate) transaction_hash: FixedBytes<32>,
+    pub(crate) log_index: u64,
+    pub(crate) timestamp: Option<DateTime<Utc>>,
+    pub(crate) received_at: DateTime<Utc>,
+}
+
+/// Signatures of the events changing positions
+pub(crate) fn position_events() -> [B256; 3] {
+    [
+        ERC721::IncreaseLiquidity::SIGNATURE_HASH,
+        ERC721::DecreaseLiquidity::SIGNATURE_HASH,
+        ERC721::Collect::SIGNATURE_HASH,
+    ]
+}
+
+/// Whether `address` is a position manager, probed through the `factory` it manages positions of
+pub(crate) async fn is_position_manager(chain_state: &ChainState, address: Address) -> bool {
+    let manager = ERC721::new(address, Arc::clone(&chain_state.provider));
+    manager
+        .factory()
+        .call()
+        .await
+        .is_ok_and(|res| res._0 != Address::ZERO)
+}
+
+/// Decode a position change and enrich it with the position
+pub(crate) async fn decode_position(chain_state: &ChainState, log: &Log) -> Option<PositionEvent> {
+    let received_at = Utc::now();
+
+    let (token_id, change) = match *log.topic0()? {
+        ERC721::IncreaseLiquidity::SIGNATURE_HASH => {
+            let event = log.log_decode::<ERC721::IncreaseLiquidity>().ok()?;
+            let event_data = event.data();
+            let change = PositionChange::IncreaseLiquidity {
+                liquidity: event_data.liquidity,
+                amount0: event_data.amount0,
+                amount1: event_data.amount1,
+            };
+            (event_data.tokenId, change)
+        }
+        ERC721::DecreaseLiquidity::SIGNATURE_HASH => {
+            let event = log.log_decode::<ERC721::DecreaseLiquidity>().ok()?;
+            let event_data = event.data();
+            let change = PositionChange::DecreaseLiquidity {
+                liquidity: event_data.liquidity,
+                amount0: event_data.amount0,
+                amount1: event_data.amount1,
+            };
+            (event_data.tokenId, change)
+        }
+        ERC721::Collect::SIGNATURE_HASH => {
+            let event = log.log_decode::<ERC721::Collect>().ok()?;
+            let event_data = event.data();
+            let change = PositionChange::Collect {
+                recipient: event_data.recipient,
+                amount0: event_data.amount0,
+                amount1: event_data.amount1,
+            };
+            (event_data.tokenId, change)
+        }
+        _ => return None,
+    };
+
+    let position = fetch_position(chain_state, log.address(), token_id, log.block_number).await;
+
+    Some(PositionEvent {
+        address: log.address(),
+        token_id,
+        change,
+        position,
+        block_number: log.block_number.unwrap_or_default(),
+        block_hash: log.block_hash.unwrap_or_default(),
+        transaction_hash: log.transaction_hash.unwrap_or_default(),
+        log_index: log.log_index.unwrap_or_default(),
+        timestamp: subscriptions::block_time(chain_state, log).await,
+        received_at,
+    })
+}
+
+/// Fetch the position `token_id` as of `block_number`.
+///
+/// Positions burned in that block are read from the block before.
+pub(crate) async fn fetch_position(
+    chain_state: &ChainState,
+    address: Address,
+    token_id: U256,
+    block_number: Option<u64>,
+) -> Option<Position> {
+    let manager = ERC721::new(address, Arc::clone(&chain_state.provider));
+
+    let block = block_number.map_or(BlockId::latest(), BlockId::number);
+    let res = match manager.positions(token_id).block(block).call().await {
+        Ok(res) => res,
+        Err(_) => {
+            let previous = BlockId::number(block_number?.checked_sub(1)?);
+            manager
+                .positions(token_id)
+                .block(previous)
+                .call()
+                .await
+                .ok()?
+        }
+    };
+
+    Some(Position {
+        token0: res.token0,
+        token1: res.token1,
+        tick_spacing: i32::try_from(res.tickSpacing).ok()?,
+        tick_lower: i32::try_from(res.tickLower).ok()?,
+        tick_upper: i32::try_from(res.tickUpper).ok()?,
+        liquidity: res.liquidity,
+        tokens_owed0: res.tokensOwed0,
+        tokens_owed1: res.tokensOwed1,
+    })
+}
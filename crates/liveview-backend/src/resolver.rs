@@ -0,0 +1,319 @@
+use std::time::Duration;
+
+use futures_util::future::{self, FutureExt};
+use serde_json::Value;
+use tracing::debug;
+use url::Url;
+
+use crate::{
+    data::{self, Gateway, GatewayStrategy, Gateways},
+    fetcher::{FetchError, Fetcher},
+    utils,
+};
+
+/// Where the content of a URI can be fetched from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Location {
+    Http(Url),
+    /// CID followed by an optional path
+    Ipfs(String),
+    /// IPNS name followed by an optional path
+    Ipns(String),
+    /// Transaction id followed by an optional path
+    Arweave(String),
+}
+
+/// Normalize the forms of IPFS, IPNS and Arweave URIs found in token metadata:
+/// `ipfs://<cid>`, `ipfs://ipfs/<cid>`, `/ipfs/<cid>`, raw CIDs, `ipns://<name>` and `ar://<id>`
+pub(crate) fn locate(uri: &str) -> Option<Location> {
+    let uri = uri.trim();
+
+    if let Some(path) = strip_scheme(uri, "ipfs:") {
+        let path = path.strip_prefix("ipfs/").unwrap_or(path);
+        return non_empty(path).map(Location::Ipfs);
+    }
+    if let Some(path) = strip_scheme(uri, "ipns:") {
+        let path = path.strip_prefix("ipns/").unwrap_or(path);
+        return non_empty(path).map(Location::Ipns);
+    }
+    if let Some(path) = strip_scheme(uri, "ar:") {
+        return non_empty(path).map(Location::Arweave);
+    }
+    if let Some(path) = uri.strip_prefix("/ipfs/") {
+        return non_empty(path).map(Location::Ipfs);
+    }
+    if is_cid(uri.split('/').next().unwrap_or_default()) {
+        return Some(Location::Ipfs(uri.to_owned()));
+    }
+
+    match Url::parse(uri) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Some(Location::Http(url)),
+        _ => None,
+    }
+}
+
+/// Strip a case insensitive scheme and the slashes following it
+fn strip_scheme<'a>(uri: &'a str, scheme: &str) -> Option<&'a str> {
+    let prefix = uri.get(..scheme.len())?;
+    if !prefix.eq_ignore_ascii_case(scheme) {
+        return None;
+    }
+
+    Some(uri[scheme.len()..].trim_start_matches('/'))
+}
+
+fn non_empty(path: &str) -> Option<String> {
+    (!path.is_empty()).then(|| path.to_owned())
+}
+
+/// Whether `path` has a `.` or `..` segment, including percent-encoded ones,
+/// ones ended by a query or a fragment and ones separated by backslashes like URL parsers do
+fn has_dot_segment(path: &str) -> bool {
+    let path = percent_encoding::percent_decode_str(path).decode_utf8_lossy();
+    path.split(['/', '\\', '?', '#'])
+        .any(|segment| segment == "." || segment == "..")
+}
+
+/// Whether `value` looks like a CIDv0 (base58 `Qm...`) or a base32 CIDv1 (`b...`)
+fn is_cid(value: &str) -> bool {
+    let is_v0 = value.len() == 46
+        && value.starts_with("Qm")
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() && !matches!(c, '0' | 'O' | 'I' | 'l'));
+    let is_v1 = value.len() >= 50
+        && value.starts_with('b')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c));
+
+    is_v0 || is_v1
+}
+
+/// Resolves IPFS, IPNS and Arweave URIs through the configured gateways
+#[derive(Debug)]
+pub(crate) struct Resolver {
+    fetcher: Fetcher,
+    ipfs: Vec<Gateway>,
+    arweave: Vec<Gateway>,
+    strategy: GatewayStrategy,
+    timeout: Duration,
+}
+
+impl Resolver {
+    pub(crate) fn new(gateways: Gateways, fetcher: &data::Fetcher) -> reqwest::Result<Self> {
+        // Gateways are configured by us and may run on our own network
+        let gateway_urls = gateways
+            .ipfs
+            .iter()
+            .chain(&gateways.arweave)
+            .map(|gateway| gateway.url.clone())
+            .collect::<Vec<_>>();
+
+        Ok(Self {
+            fetcher: Fetcher::new(fetcher, &gateway_urls)?,
+            ipfs: gateways.ipfs,
+            arweave: gateways.arweave,
+            strategy: gateways.strategy,
+            timeout: Duration::from_millis(gateways.timeout_ms),
+        })
+    }
+
+    /// URLs serving `location`, in order of preference
+    fn candidates(&self, location: &Location, public_only: bool) -> Vec<Url> {
+        let (gateways, prefix, path) = match location {
+            Location::Http(url) => return vec![url.to_owned()],
+            Location::Ipfs(path) => (&self.ipfs, "ipfs/", path),
+            Location::Ipns(path) => (&self.ipfs, "ipns/", path),
+            Location::Arweave(path) => (&self.arweave, "", path),
+        };
+
+        // A path climbing out of the prefix would reach other endpoints of the gateway
+        if has_dot_segment(path) {
+            debug!(path, "Rejected a path with dot segments");
+            return vec![];
+        }
+
+        gateways
+            .iter()
+            .filter(|gateway| gateway.public || !public_only)
+            .filter_map(|gateway| {
+                let base = gateway.url.as_str().trim_end_matches('/');
+                let url = Url::parse(&format!("{base}/{prefix}{path}")).ok()?;
+
+                // Only URLs under the prefix are trusted as gateway requests
+                let base_path = gateway.url.path().trim_end_matches('/');
+                url.path()
+                    .starts_with(&format!("{base_path}/{prefix}"))
+                    .then_some(url)
+            })
+            .collect()
+    }
+
+    /// Rewrite `uri` into a link clients can load, data URIs are kept as is
+    pub(crate) fn public_url(&self, uri: &str) -> Option<String> {
+        if utils::is_data_uri(uri) {
+            return Some(uri.to_owned());
+        }
+
+        let location = locate(uri)?;
+        self.candidates(&location, true)
+            .into_iter()
+            .next()
+            .map(String::from)
+    }
+
+    /// Fetch the JSON document at `uri` from the gateways serving it.
+    ///
+    /// `refresh` asks gateways and CDNs for a fresh copy.
+    /// Returns the error of the last gateway tried when all of them fail.
+    pub(crate) async fn fetch_json(&self, uri: &str, refresh: bool) -> Result<Value, FetchError> {
+        let location = locate(uri).ok_or(FetchError::UnsupportedUri)?;
+        let candidates = self.candidates(&location, false);
+        // Plain URLs come from the token URI and are never trusted
+        let via_gateway = !matches!(location, Location::Http(_));
+
+        let mut last_err = FetchError::UnsupportedUri;
+        match self.strategy {
+            GatewayStrategy::Failover => {
+                for url in candidates {
+                    match self
+                        .fetcher
+                        .get_json(url.clone(), self.timeout, refresh, via_gateway)
+                        .await
+                    {
+                        Ok(json) => return Ok(json),
+                        Err(err) => {
+                            debug!(%url, %err, "Metadata request failed");
+                            last_err = err;
+                        }
+                    }
+                }
+            }
+            GatewayStrategy::Race if !candidates.is_empty() => {
+                let requests = candidates.into_iter().map(|url| {
+                    self.fetcher
+                        .get_json(url, self.timeout, refresh, via_gateway)
+                        .boxed()
+                });
+                match future::select_ok(requests).await {
+                    Ok((json, _)) => return Ok(json),
+                    Err(err) => {
+                        debug!(uri, %err, "All metadata requests failed");
+                        last_err = err;
+                    }
+                }
+            }
+            GatewayStrategy::Race => {}
+        }
+
+        Err(last_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CID_V0: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const CID_V1: &str = "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzdi";
+
+    #[test]
+    fn locates_uris() {
+        let ipfs = |path: &str| Some(Location::Ipfs(path.to_owned()));
+        for (uri, expected) in [
+            (
+                format!("ipfs://{CID_V0}/1.json"),
+                ipfs(&format!("{CID_V0}/1.json")),
+            ),
+            (
+                format!("ipfs://ipfs/{CID_V0}/1.json"),
+                ipfs(&format!("{CID_V0}/1.json")),
+            ),
+            (format!("IPFS://{CID_V1}"), ipfs(CID_V1)),
+            (format!("ipfs:/{CID_V1}"), ipfs(CID_V1)),
+            (format!("/ipfs/{CID_V0}/1"), ipfs(&format!("{CID_V0}/1"))),
+            (format!(" {CID_V0}/1 "), ipfs(&format!("{CID_V0}/1"))),
+            (CID_V1.to_owned(), ipfs(CID_V1)),
+            (
+                "ipns://ipns/example.eth/1".to_owned(),
+                Some(Location::Ipns("example.eth/1".to_owned())),
+            ),
+            (
+                "ar://Lp0Y0bVMVWpPXqQq6eqMnKzFHv0rhbCJOiDzcZsm0Fs".to_owned(),
+                Some(Location::Arweave(
+                    "Lp0Y0bVMVWpPXqQq6eqMnKzFHv0rhbCJOiDzcZsm0Fs".to_owned(),
+                )),
+            ),
+            (
+                "https://example.com/1.json".to_owned(),
+                Some(Location::Http(
+                    Url::parse("https://example.com/1.json").unwrap(),
+                )),
+            ),
+            ("ipfs://".to_owned(), None),
+            ("ipfs://ipfs/".to_owned(), None),
+            ("ar://".to_owned(), None),
+            ("ftp://example.com/1.json".to_owned(), None),
+            ("not a uri".to_owned(), None),
+        ] {
+            assert_eq!(locate(&uri), expected, "{uri}");
+        }
+    }
+
+    #[test]
+    fn keeps_paths_under_the_gateway_prefix() {
+        let gateways = Gateways {
+            ipfs: vec![Gateway {
+                url: Url::parse("http://127.0.0.1:8080/gateway/").unwrap(),
+                public: false,
+            }],
+            ..Gateways::default()
+        };
+        let resolver = Resolver::new(gateways, &data::Fetcher::default()).unwrap();
+        let candidates = |path: &str| resolver.candidates(&Location::Ipfs(path.to_owned()), false);
+
+        assert_eq!(
+            candidates(&format!("{CID_V0}/1.json")),
+            [Url::parse(&format!(
+                "http://127.0.0.1:8080/gateway/ipfs/{CID_V0}/1.json"
+            ))
+            .unwrap()]
+        );
+        for path in [
+            format!("{CID_V0}/../../admin"),
+            "../api/v0/shutdown".to_owned(),
+            format!("{CID_V0}/%2e%2e/%2E%2E/admin"),
+            format!("{CID_V0}/.%2e/admin"),
+            format!("{CID_V0}/..%5C..%5Cadmin"),
+            format!("{CID_V0}\\..\\admin"),
+            format!("{CID_V0}/..?x"),
+            format!("{CID_V0}/./1.json"),
+        ] {
+            assert!(candidates(&path).is_empty(), "{path}");
+        }
+    }
+
+    #[test]
+    fn recognizes_cids() {
+        for (value, expected) in [
+            (CID_V0, true),
+            (CID_V1, true),
+            // Wrong length, or characters outside of base58 and base32
+            (&CID_V0[..45], false),
+            ("Qm0wAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG", false),
+            (&CID_V1[..49], false),
+            (
+                "bafybeigdyrzt5sfp7udm7hu76uh7y26nf3efuylqabf3oclgtqy55fbzd1",
+                false,
+            ),
+            (
+                "BAFYBEIGDYRZT5SFP7UDM7HU76UH7Y26NF3EFUYLQABF3OCLGTQY55FBZDI",
+                false,
+            ),
+            ("example.com", false),
+        ] {
+            assert_eq!(is_cid(value), expected, "{value}");
+        }
+    }
+}
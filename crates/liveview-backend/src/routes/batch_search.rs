@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use alloy::primitives::Address;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{ErrorResponse, Result},
+    Json,
+};
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+
+use super::search::{self, SuccessData, CALLS_PER_COLLECTION};
+use crate::{
+    interfaces::Multicall,
+    state::{AppState, ChainState},
+};
+
+/// Maximum number of addresses searched in one request
+const MAX_ADDRESSES: usize = 50;
+/// Maximum number of addresses searched across all chains of one request
+const MAX_LOOKUPS: usize = 250;
+
+#[derive(Deserialize)]
+pub(crate) struct BatchSearchRequest {
+    pub(crate) addresses: Vec<Address>,
+    /// Chain names or ids, all configured chains when omitted
+    pub(crate) chains: Option<Vec<String>>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ChainResults {
+    pub(crate) chain: String,
+    /// Set when the whole chain could not be searched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    pub(crate) results: Vec<AddressResult>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum AddressResult {
+    Found {
+        address: Address,
+        #[serde(flatten)]
+        data: Box<SuccessData>,
+    },
+    Error {
+        address: Address,
+        message: String,
+    },
+}
+
+#[axum::debug_handler]
+pub(crate) async fn batch_search(
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<BatchSearchRequest>,
+) -> Result<Json<Vec<ChainResults>>> {
+    if request.addresses.is_empty() || request.addresses.len() > MAX_ADDRESSES {
+        return Err(ErrorResponse::from((
+            StatusCode::BAD_REQUEST,
+            format!("Expected between 1 and {MAX_ADDRESSES} addresses"),
+        )));
+    }
+
+    let keys = match request.chains {
+        Some(chains) => chains,
+        None => {
+            let mut chains = state.chains.keys().cloned().collect::<Vec<_>>();
+            chains.sort();
+            chains
+        }
+    };
+
+    // Resolve the chains up front, a name and an id of the same chain are only searched once
+    let mut chains = Vec::<(String, Arc<ChainState>)>::with_capacity(keys.len());
+    for key in keys {
+        let chain_state = match state.chain(&key) {
+            Ok(chain_state) => chain_state,
+            Err(err) => {
+                return Err(ErrorResponse::from((
+                    StatusCode::BAD_REQUEST,
+                    err.to_string(),
+                )));
+            }
+        };
+        if chains
+            .iter()
+            .all(|(_, chain)| chain.chain_id != chain_state.chain_id)
+        {
+            chains.push((key, chain_state));
+        }
+    }
+
+    if request.addresses.len() * chains.len() > MAX_LOOKUPS {
+        return Err(ErrorResponse::from((
+            StatusCode::BAD_REQUEST,
+            format!("Expected at most {MAX_LOOKUPS} addresses across all chains"),
+        )));
+    }
+
+    let results = join_all(chains.into_iter().map(|(chain, chain_state)| {
+        let addresses = &request.addresses;
+        async move { search_chain(chain, &chain_state, addresses).await }
+    }))
+    .await;
+
+    Ok(Json(results))
+}
+
+/// Search all addresses on one chain through a single multicall
+async fn search_chain(
+    chain: String,
+    chain_state: &ChainState,
+    addresses: &[Address],
+) -> ChainResults {
+    let multicall = Multicall::new(
+        chain_state.multicall_address,
+        Arc::clone(&chain_state.provider),
+    );
+
+    let calls = addresses
+        .iter()
+        .flat_map(|address| search::collection_calls(chain_state, *address))
+        .collect();
+
+    let res = match multicall.multicall(calls).call().await {
+        Ok(res) => res.returnData,
+        Err(_) => {
+            return ChainResults {
+                chain,
+                error: Some("Failed to call fetch data".to_owned()),
+                results: Vec::new(),
+            };
+        }
+    };
+
+    let results = join_all(addresses.iter().zip(res.chunks(CALLS_PER_COLLECTION)).map(
+        |(address, res)| async move {
+            match search::decode_collection(res) {
+                Ok(data) => AddressResult::Found {
+                    address: *address,
+                    data: Box::new(search::with_contract_metadata(chain_state, data).await),
+                },
+                Err((_, message)) => AddressResult::Error {
+                    address: *address,
+                    message,
+                },
+            }
+        },
+    ))
+    .await;
+
+    ChainResults {
+        chain,
+        error: None,
+        results,
+    }
+}
@@ -0,0 +1,11 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+
+use crate::{cache::CachesStats, state::AppState};
+
+/// Hit and miss counters of the caches
+#[axum::debug_handler]
+pub(crate) async fn cache(State(state): State<Arc<AppState>>) -> Json<CachesStats> {
+    Json(state.caches.stats())
+}
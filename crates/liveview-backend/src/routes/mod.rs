@@ -1 +1,4 @@
+pub mod batch_search;
+pub mod cache;
 pub mod search;
+pub mod token;
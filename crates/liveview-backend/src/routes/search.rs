@@ -13,21 +13,71 @@ use axum::{
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    data::ChainType,
-    interfaces::{Multicall, ERC721},
-    state::AppState,
+    interfaces::{ContractMetadata, Multicall, Ownable, ERC20, ERC2981, ERC721},
+    metadata,
+    state::{AppState, ChainState},
+    tokens::{self, TokenStandard, ERC1155_INTERFACE_ID, ERC721_INTERFACE_ID},
 };
 
+/// Sale price used to read royalties as basis points
+const ROYALTY_SALE_PRICE: u64 = 10_000;
+
+/// Optional interfaces reported by `supportsInterface`
+const INTERFACES: [(Interface, FixedBytes<4>); 5] = [
+    (
+        Interface::Erc721Metadata,
+        FixedBytes([0x5b, 0x5e, 0x13, 0x9f]),
+    ),
+    (
+        Interface::Erc721Enumerable,
+        FixedBytes([0x78, 0x0e, 0x9d, 0x63]),
+    ),
+    (Interface::Erc2981, FixedBytes([0x2a, 0x55, 0x20, 0x5a])),
+    (Interface::Erc4906, FixedBytes([0x49, 0x06, 0x49, 0x06])),
+    (Interface::Erc5192, FixedBytes([0xb4, 0x5a, 0x3c, 0x0e])),
+];
+
+/// Number of calls made by `collection_calls`
+pub(crate) const CALLS_PER_COLLECTION: usize = 9 + INTERFACES.len();
+
 #[derive(Deserialize)]
 pub(crate) struct SearchQuery {
-    pub(crate) chain: ChainType,
+    pub(crate) chain: String,
     pub(crate) address: Address,
 }
 
 #[derive(Serialize)]
 pub(crate) struct SuccessData {
+    pub(crate) standard: TokenStandard,
     pub(crate) name: String,
     pub(crate) symbol: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) decimals: Option<u8>,
+    pub(crate) total_supply: Option<U256>,
+    /// Owner of the contract (Ownable)
+    pub(crate) owner: Option<Address>,
+    pub(crate) contract_uri: Option<String>,
+    /// Collection metadata found at `contract_uri`
+    pub(crate) contract_metadata: Option<serde_json::Value>,
+    pub(crate) royalty: Option<Royalty>,
+    pub(crate) interfaces: Vec<Interface>,
+}
+
+/// Default ERC2981 royalty of the collection
+#[derive(Serialize)]
+pub(crate) struct Royalty {
+    pub(crate) receiver: Address,
+    pub(crate) basis_points: U256,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Interface {
+    Erc721Metadata,
+    Erc721Enumerable,
+    Erc2981,
+    Erc4906,
+    Erc5192,
 }
 
 // #[derive(Serialize)]
@@ -40,53 +90,26 @@ pub(crate) async fn search(
     State(state): State<Arc<AppState>>,
     Query(query): Query<SearchQuery>,
 ) -> Result<Json<SuccessData>> {
-    let chain_state = match query.chain {
-        ChainType::Mainnet => &state.mainnet,
-        ChainType::Base => &state.base,
-        ChainType::Arbitrum => &state.arbitrum,
-        ChainType::Optimism => &state.optimism,
-        ChainType::Polygon => &state.polygon,
-        ChainType::Bsc => &state.bsc,
-    };
-
-    let erc721 = ERC721::new(query.address, Arc::clone(&chain_state.provider));
-
-    let supports_interface = match erc721
-        .supportsInterface(FixedBytes(
-            [0x80, 0xac, 0x58, 0xcd], /* ERC721.supportsInterface */
-        ))
-        .call()
-        .await
-    {
-        Ok(res) => res._0,
-        Err(_) => false, /* Error means that the address doesn't support the interface */
+    let chain_state = match state.chain(&query.chain) {
+        Ok(chain_state) => chain_state,
+        Err(err) => {
+            return Err(ErrorResponse::from((
+                StatusCode::BAD_REQUEST,
+                err.to_string(),
+            )));
+        }
     };
-    if !supports_interface {
-        return Err(ErrorResponse::from((
-            StatusCode::BAD_REQUEST,
-            "Invalid address".to_owned(),
-        )));
-    }
 
     let multicall = Multicall::new(
         chain_state.multicall_address,
         Arc::clone(&chain_state.provider),
     );
 
-    let calls = vec![
-        Multicall::Call {
-            target: query.address,
-            gasLimit: U256::MAX,
-            callData: erc721.name().calldata().to_owned(),
-        },
-        Multicall::Call {
-            target: query.address,
-            gasLimit: U256::MAX,
-            callData: erc721.symbol().calldata().to_owned(),
-        },
-    ];
-
-    let res = match multicall.multicall(calls).call().await {
+    let res = match multicall
+        .multicall(collection_calls(&chain_state, query.address))
+        .call()
+        .await
+    {
         Ok(res) => res.returnData,
         Err(_) => {
             return Err(ErrorResponse::from((
@@ -96,24 +119,154 @@ pub(crate) async fn search(
         }
     };
 
-    let name = match ERC721::nameCall::abi_decode_returns(&res[0].returnData, false) {
-        Ok(decode_res) => decode_res._0,
-        Err(_) => {
-            return Err(ErrorResponse::from((
+    let data = match decode_collection(&res) {
+        Ok(data) => data,
+        Err(err) => return Err(ErrorResponse::from(err)),
+    };
+
+    Ok(Json(with_contract_metadata(&chain_state, data).await))
+}
+
+/// Fetch the collection metadata pointed to by the contract URI, if any
+pub(crate) async fn with_contract_metadata(
+    chain_state: &ChainState,
+    mut data: SuccessData,
+) -> SuccessData {
+    if let Some(contract_uri) = &data.contract_uri {
+        data.contract_metadata =
+            metadata::fetch_contract_metadata(&chain_state.resolver, contract_uri).await;
+    }
+
+    data
+}
+
+/// Calls fetching the details of the collection at `address`
+pub(crate) fn collection_calls(chain_state: &ChainState, address: Address) -> Vec<Multicall::Call> {
+    let provider = Arc::clone(&chain_state.provider);
+    let erc721 = ERC721::new(address, Arc::clone(&provider));
+    let erc20 = ERC20::new(address, Arc::clone(&provider));
+    let ownable = Ownable::new(address, Arc::clone(&provider));
+    let contract_metadata = ContractMetadata::new(address, Arc::clone(&provider));
+    let erc2981 = ERC2981::new(address, provider);
+
+    let mut call_data = vec![
+        erc721
+            .supportsInterface(ERC721_INTERFACE_ID)
+            .calldata()
+            .to_owned(),
+        erc721
+            .supportsInterface(ERC1155_INTERFACE_ID)
+            .calldata()
+            .to_owned(),
+        erc721.name().calldata().to_owned(),
+        erc721.symbol().calldata().to_owned(),
+        erc20.decimals().calldata().to_owned(),
+        erc20.totalSupply().calldata().to_owned(),
+        ownable.owner().calldata().to_owned(),
+        contract_metadata.contractURI().calldata().to_owned(),
+        erc2981
+            .royaltyInfo(U256::ZERO, U256::from(ROYALTY_SALE_PRICE))
+            .calldata()
+            .to_owned(),
+    ];
+    for (_, interface_id) in INTERFACES {
+        call_data.push(erc721.supportsInterface(interface_id).calldata().to_owned());
+    }
+
+    call_data
+        .into_iter()
+        .map(|call_data| Multicall::Call {
+            target: address,
+            gasLimit: U256::MAX,
+            callData: call_data,
+        })
+        .collect()
+}
+
+/// Decode the results of `collection_calls`, without the contract metadata
+pub(crate) fn decode_collection(
+    res: &[Multicall::Result],
+) -> std::result::Result<SuccessData, (StatusCode, String)> {
+    // Failed calls are treated like unimplemented functions
+    let returned = |index: usize| {
+        let res = &res[index];
+        res.success.then_some(&res.returnData[..])
+    };
+    let supports = |index: usize| {
+        returned(index)
+            .and_then(|data| ERC721::supportsInterfaceCall::abi_decode_returns(data, true).ok())
+            .is_some_and(|res| res._0)
+    };
+
+    let decimals = returned(4)
+        .and_then(|data| ERC20::decimalsCall::abi_decode_returns(data, true).ok())
+        .map(|res| res._0);
+    // Contracts implementing neither interface may still be ERC20 tokens
+    let (standard, decimals) = if supports(0) {
+        (TokenStandard::Erc721, None)
+    } else if supports(1) {
+        (TokenStandard::Erc1155, None)
+    } else if decimals.is_some() {
+        (TokenStandard::Erc20, decimals)
+    } else {
+        return Err((StatusCode::BAD_REQUEST, "Invalid address".to_owned()));
+    };
+
+    let name = match returned(2).and_then(tokens::decode_string) {
+        Some(name) => name,
+        // Name and symbol are optional for ERC1155
+        None if standard == TokenStandard::Erc1155 => String::new(),
+        None => {
+            return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Failed to decode name".to_owned(),
-            )));
+            ));
         }
     };
-    let symbol = match ERC721::symbolCall::abi_decode_returns(&res[1].returnData, false) {
-        Ok(decode_res) => decode_res._0,
-        Err(_) => {
-            return Err(ErrorResponse::from((
+    let symbol = match returned(3).and_then(tokens::decode_string) {
+        Some(symbol) => symbol,
+        None if standard == TokenStandard::Erc1155 => String::new(),
+        None => {
+            return Err((
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Failed to decode symbol".to_owned(),
-            )));
+            ));
         }
     };
 
-    Ok(Json(SuccessData { name, symbol }))
+    let total_supply = returned(5)
+        .and_then(|data| ERC20::totalSupplyCall::abi_decode_returns(data, true).ok())
+        .map(|res| res._0);
+    let owner = returned(6)
+        .and_then(|data| Ownable::ownerCall::abi_decode_returns(data, true).ok())
+        .map(|res| res._0);
+    let contract_uri = returned(7)
+        .and_then(|data| ContractMetadata::contractURICall::abi_decode_returns(data, true).ok())
+        .map(|res| res._0)
+        .filter(|contract_uri| !contract_uri.is_empty());
+    let royalty = returned(8)
+        .and_then(|data| ERC2981::royaltyInfoCall::abi_decode_returns(data, true).ok())
+        .map(|res| Royalty {
+            receiver: res.receiver,
+            basis_points: res.royaltyAmount,
+        });
+    let interfaces = INTERFACES
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| supports(9 + index))
+        .map(|(_, (interface, _))| *interface)
+        .collect();
+
+    Ok(SuccessData {
+        standard,
+        name,
+        symbol,
+        decimals,
+        total_supply,
+        owner,
+        contract_uri,
+        contract_metadata: None,
+        royalty,
+        interfaces,
+    })
 }
@@ -0,0 +1,158 @@
+use std::sync::Arc;
+
+use alloy::primitives::{Address, U256};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{ErrorResponse, Result},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    interfaces::ERC721,
+    metadata::{self, TokenMetadata},
+    state::AppState,
+    tokens::{self, InvalidReason, TokenStandard, ValidationStatus},
+    utils::MetadataType,
+};
+
+#[derive(Deserialize)]
+pub(crate) struct TokenQuery {
+    pub(crate) chain: String,
+    pub(crate) address: Address,
+    pub(crate) token_id: U256,
+    /// Bypass cached metadata, e.g. right after a reveal
+    #[serde(default)]
+    pub(crate) refresh: bool,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SuccessData {
+    pub(crate) address: Address,
+    pub(crate) token_id: U256,
+    pub(crate) standard: TokenStandard,
+    pub(crate) name: String,
+    pub(crate) symbol: String,
+    /// Only ERC721 tokens have a single owner
+    pub(crate) owner: Option<Address>,
+    pub(crate) token_uri: Option<String>,
+    pub(crate) metadata: Option<TokenMetadata>,
+    /// Metadata JSON as served by the token URI
+    pub(crate) raw_metadata: Option<serde_json::Value>,
+    pub(crate) image: Option<String>,
+    pub(crate) image_type: Option<MetadataType>,
+    pub(crate) image_media_type: Option<String>,
+    /// Why the metadata could not be resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata_error: Option<String>,
+}
+
+#[axum::debug_handler]
+pub(crate) async fn token(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<TokenQuery>,
+) -> Result<Json<SuccessData>> {
+    let chain_state = match state.chain(&query.chain) {
+        Ok(chain_state) => chain_state,
+        Err(err) => {
+            return Err(ErrorResponse::from((
+                StatusCode::BAD_REQUEST,
+                err.to_string(),
+            )));
+        }
+    };
+
+    let validation = tokens::lookup(&chain_state, &[query.address])
+        .await
+        .remove(0);
+    if let ValidationStatus::Invalid {
+        reason: InvalidReason::LookupFailed,
+        message,
+    } = validation.status
+    {
+        return Err(ErrorResponse::from((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            message,
+        )));
+    }
+    let token_data = match validation.token_data() {
+        Some(token_data) if token_data.standard != TokenStandard::Erc20 => token_data,
+        _ => {
+            return Err(ErrorResponse::from((
+                StatusCode::BAD_REQUEST,
+                "Address is not an ERC721 or ERC1155 contract".to_owned(),
+            )));
+        }
+    };
+
+    let owner = match token_data.standard {
+        TokenStandard::Erc721 => {
+            let token = ERC721::new(query.address, Arc::clone(&chain_state.provider));
+            match token.ownerOf(query.token_id).call().await {
+                Ok(res) => Some(res._0),
+                // Nonexistent tokens revert
+                Err(_) => {
+                    return Err(ErrorResponse::from((
+                        StatusCode::NOT_FOUND,
+                        "Token does not exist".to_owned(),
+                    )));
+                }
+            }
+        }
+        TokenStandard::Erc20 | TokenStandard::Erc1155 => None,
+    };
+
+    let info = metadata::lookup(
+        &chain_state,
+        token_data.standard,
+        query.address,
+        query.token_id,
+        query.refresh,
+    )
+    .await;
+    let (token_uri, resolved, metadata_error) = match info {
+        Ok(info) => (
+            Some(info.token_uri.clone()),
+            Some(info.resolved.clone()),
+            None,
+        ),
+        Err(err) => {
+            // Report the token URI even when its metadata can't be resolved
+            let token_uri = metadata::fetch_token_uri(
+                &chain_state.provider,
+                token_data.standard,
+                query.address,
+                query.token_id,
+            )
+            .await;
+            (token_uri, None, Some(err.to_string()))
+        }
+    };
+    let (metadata, raw_metadata, image, image_type, image_media_type) = match resolved {
+        Some(resolved) => (
+            resolved.metadata,
+            resolved.raw,
+            resolved.image,
+            resolved.image_type,
+            resolved.image_media_type,
+        ),
+        None => (None, None, None, None, None),
+    };
+
+    Ok(Json(SuccessData {
+        address: query.address,
+        token_id: query.token_id,
+        standard: token_data.standard,
+        name: token_data.name,
+        symbol: token_data.symbol,
+        owner,
+        token_uri,
+        metadata,
+        raw_metadata,
+        image,
+        image_type,
+        image_media_type,
+        metadata_error,
+    }))
+}
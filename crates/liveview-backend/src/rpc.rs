@@ -0,0 +1,311 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    providers::{Provider, ProviderBuilder, RootProvider},
+    pubsub::Subscription,
+    rpc::{
+        json_rpc::{RequestPacket, ResponsePacket},
+        types::{Filter, Log},
+    },
+    transports::{BoxTransport, TransportError, TransportErrorKind, TransportFut},
+};
+use futures_util::future;
+use tower::Service;
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Delay before a failing endpoint is tried again, doubled on every consecutive failure
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+/// Upper bound of the delay before a failing endpoint is tried again
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(300);
+/// Time allowed to connect to an endpoint
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time allowed for an endpoint to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    retry_at: Option<Instant>,
+}
+
+/// A single RPC endpoint of a chain
+#[derive(Debug)]
+pub(crate) struct Endpoint {
+    pub(crate) url: Url,
+    /// Lower values are preferred
+    pub(crate) priority: u32,
+    /// Connected lazily so that an endpoint which is down at startup can join later
+    provider: tokio::sync::Mutex<Option<Arc<RootProvider<BoxTransport>>>>,
+    health: Mutex<Health>,
+}
+
+impl Endpoint {
+    fn new(url: Url, priority: u32) -> Self {
+        Self {
+            url,
+            priority,
+            provider: tokio::sync::Mutex::new(None),
+            health: Mutex::new(Health::default()),
+        }
+    }
+
+    /// Whether the endpoint supports `eth_subscribe`
+    pub(crate) fn is_pubsub(&self) -> bool {
+        matches!(self.url.scheme(), "ws" | "wss")
+    }
+
+    pub(crate) fn is_healthy(&self) -> bool {
+        let health = self.health.lock().unwrap();
+        health
+            .retry_at
+            .is_none_or(|retry_at| retry_at <= Instant::now())
+    }
+
+    fn retry_at(&self) -> Option<Instant> {
+        self.health.lock().unwrap().retry_at
+    }
+
+    pub(crate) async fn provider(&self) -> Result<Arc<RootProvider<BoxTransport>>, TransportError> {
+        let mut provider = self.provider.lock().await;
+        if let Some(provider) = provider.as_ref() {
+            return Ok(Arc::clone(provider));
+        }
+
+        // Bounded so that a hanging endpoint doesn't hold the lock and fails over instead
+        let connected = match tokio::time::timeout(
+            CONNECT_TIMEOUT,
+            ProviderBuilder::new().on_builtin(self.url.as_str()),
+        )
+        .await
+        {
+            Ok(connected) => Arc::new(connected?),
+            Err(_) => return Err(TransportErrorKind::custom_str("Connection timed out")),
+        };
+        *provider = Some(Arc::clone(&connected));
+
+        Ok(connected)
+    }
+
+    pub(crate) fn mark_success(&self) {
+        let mut health = self.health.lock().unwrap();
+        if health.consecutive_failures > 0 {
+            info!(url = %self.url, "RPC endpoint recovered");
+        }
+        *health = Health::default();
+    }
+
+    pub(crate) async fn mark_failure(&self, err: &TransportError) {
+        let delay = {
+            let mut health = self.health.lock().unwrap();
+            health.consecutive_failures += 1;
+
+            let delay = RETRY_BASE_DELAY
+                .saturating_mul(1 << (health.consecutive_failures - 1).min(16))
+                .min(RETRY_MAX_DELAY);
+            health.retry_at = Some(Instant::now() + delay);
+
+            delay
+        };
+        warn!(url = %self.url, %err, ?delay, "RPC endpoint failed");
+
+        // A closed websocket never comes back on its own, reconnect on next use
+        if matches!(
+            err,
+            TransportError::Transport(
+                TransportErrorKind::BackendGone | TransportErrorKind::PubsubUnavailable
+            )
+        ) {
+            self.provider.lock().await.take();
+        }
+    }
+
+    /// Like `mark_failure` but for RPC level errors which mean the endpoint is throttling us
+    async fn mark_throttled(&self) {
+        self.mark_failure(&TransportErrorKind::custom_str("rate limited"))
+            .await;
+    }
+}
+
+/// The RPC endpoints of a chain ordered by priority
+#[derive(Debug)]
+pub(crate) struct Endpoints {
+    endpoints: Vec<Arc<Endpoint>>,
+}
+
+impl Endpoints {
+    pub(crate) fn new(urls: impl IntoIterator<Item = (Url, u32)>) -> Self {
+        let mut endpoints = urls
+            .into_iter()
+            .map(|(url, priority)| Arc::new(Endpoint::new(url, priority)))
+            .collect::<Vec<_>>();
+        endpoints.sort_by_key(|endpoint| endpoint.priority);
+
+        Self { endpoints }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Arc<Endpoint>> {
+        self.endpoints.iter()
+    }
+
+    /// Endpoints in the order they should be tried: healthy ones by priority,
+    /// then failing ones by how soon they may be retried
+    pub(crate) fn candidates(&self) -> Vec<Arc<Endpoint>> {
+        let (mut healthy, mut failing): (Vec<_>, Vec<_>) = self
+            .endpoints
+            .iter()
+            .map(Arc::clone)
+            .partition(|endpoint| endpoint.is_healthy());
+        failing.sort_by_key(|endpoint| endpoint.retry_at());
+        healthy.append(&mut failing);
+
+        healthy
+    }
+
+    /// Send a request to the best endpoint, failing over to the next one on transport errors
+    async fn request(&self, req: RequestPacket) -> Result<ResponsePacket, TransportError> {
+        let mut last_res = None;
+
+        for endpoint in self.candidates() {
+            let provider = match endpoint.provider().await {
+                Ok(provider) => provider,
+                Err(err) => {
+                    endpoint.mark_failure(&err).await;
+                    last_res = Some(Err(err));
+                    continue;
+                }
+            };
+
+            let mut transport = provider.client().transport().clone();
+            let call = async {
+                future::poll_fn(|cx| transport.poll_ready(cx)).await?;
+                transport.call(req.clone()).await
+            };
+            let res = with_timeout(REQUEST_TIMEOUT, call).await;
+
+            match res {
+                Ok(res) if res.iter_errors().any(|err| err.is_retry_err()) => {
+                    endpoint.mark_throttled().await;
+                    last_res = Some(Ok(res));
+                }
+                Ok(res) => {
+                    endpoint.mark_success();
+                    return Ok(res);
+                }
+                Err(err) => {
+                    endpoint.mark_failure(&err).await;
+                    last_res = Some(Err(err));
+                }
+            }
+        }
+
+        last_res
+            .unwrap_or_else(|| Err(TransportErrorKind::custom_str("No RPC endpoint configured")))
+    }
+
+    /// Subscribe to logs on the best endpoint which supports subscriptions
+    pub(crate) async fn subscribe_logs(
+        &self,
+        filter: &Filter,
+    ) -> Result<(Subscription<Log>, Arc<Endpoint>), TransportError> {
+        let mut last_err = None;
+
+        for endpoint in self.candidates() {
+            if !endpoint.is_pubsub() {
+                continue;
+            }
+
+            let res = match endpoint.provider().await {
+                Ok(provider) => {
+                    with_timeout(REQUEST_TIMEOUT, provider.subscribe_logs(filter)).await
+                }
+                Err(err) => Err(err),
+            };
+            match res {
+                Ok(sub) => {
+                    endpoint.mark_success();
+                    debug!(url = %endpoint.url, "Subscribed to logs");
+                    return Ok((sub, endpoint));
+                }
+                Err(err) => {
+                    endpoint.mark_failure(&err).await;
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(TransportErrorKind::pubsub_unavailable))
+    }
+
+    /// Periodically probe failing endpoints so that traffic fails back once they recover
+    pub(crate) fn spawn_health_check(self: &Arc<Self>, interval: Duration) {
+        let endpoints = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+
+                for endpoint in endpoints.iter() {
+                    let failing = endpoint.health.lock().unwrap().consecutive_failures > 0;
+                    if !failing || !endpoint.is_healthy() {
+                        continue;
+                    }
+
+                    let res = match endpoint.provider().await {
+                        Ok(provider) => with_timeout(REQUEST_TIMEOUT, provider.get_block_number())
+                            .await
+                            .map(|_| ()),
+                        Err(err) => Err(err),
+                    };
+                    match res {
+                        Ok(()) => endpoint.mark_success(),
+                        Err(err) => endpoint.mark_failure(&err).await,
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Run `fut`, failing with a transport error once `timeout` elapses
+async fn with_timeout<T>(
+    timeout: Duration,
+    fut: impl Future<Output = Result<T, TransportError>>,
+) -> Result<T, TransportError> {
+    match tokio::time::timeout(timeout, fut).await {
+        Ok(res) => res,
+        Err(_) => Err(TransportErrorKind::custom_str("Request timed out")),
+    }
+}
+
+/// Transport that spreads requests over the endpoints of a chain
+#[derive(Debug, Clone)]
+pub(crate) struct FailoverTransport {
+    endpoints: Arc<Endpoints>,
+}
+
+impl FailoverTransport {
+    pub(crate) fn new(endpoints: Arc<Endpoints>) -> Self {
+        Self { endpoints }
+    }
+}
+
+impl Service<RequestPacket> for FailoverTransport {
+    type Response = ResponsePacket;
+    type Error = TransportError;
+    type Future = TransportFut<'static>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: RequestPacket) -> Self::Future {
+        let endpoints = Arc::clone(&self.endpoints);
+        Box::pin(async move { endpoints.request(req).await })
+    }
+}
@@ -0,0 +1,210 @@
+use alloy::{
+    primitives::{address, Address, U256},
+    rpc::types::Log,
+    sol_types::SolEvent,
+};
+
+use super::{emitted_by, Fee, Sale, SaleDecoder};
+use crate::interfaces::{
+    Blur::OrdersMatched,
+    BlurV2::{Execution721MakerFeePacked, Execution721Packed, Execution721TakerFeePacked},
+};
+
+/// The original exchange and the second version
+const EXCHANGES: &[Address] = &[
+    address!("000000000000Ad05Ccc4F10045630fb830B95127"),
+    address!("b2ecfE4E4D61f8790bbb9DE2D1259B9e2410CEA5"),
+];
+/// Bids on Blur are paid in its wrapped ETH
+const BLUR_POOL: Address = address!("0000000000A39bb272e79075ade125fd351887Ac");
+/// Fee rates are in basis points
+const RATE_BASE: u64 = 10_000;
+
+/// Blur exchange, both the original `OrdersMatched` and the packed events of its second version
+pub(super) struct Blur;
+
+impl SaleDecoder for Blur {
+    fn decode(&self, logs: &[Log], collection: Address, token_id: U256) -> Option<Sale> {
+        emitted_by(logs, EXCHANGES).find_map(|log| match *log.topic0()? {
+            OrdersMatched::SIGNATURE_HASH => decode_v1(log, collection, token_id),
+            Execution721Packed::SIGNATURE_HASH => {
+                let event = log.log_decode::<Execution721Packed>().ok()?;
+                let event = event.data();
+                decode_packed(
+                    event.tokenIdListingIndexTrader,
+                    event.collectionPriceSide,
+                    None,
+                    collection,
+                    token_id,
+                )
+            }
+            Execution721TakerFeePacked::SIGNATURE_HASH => {
+                let event = log.log_decode::<Execution721TakerFeePacked>().ok()?;
+                let event = event.data();
+                decode_packed(
+                    event.tokenIdListingIndexTrader,
+                    event.collectionPriceSide,
+                    Some(event.takerFeeRecipientRate),
+                    collection,
+                    token_id,
+                )
+            }
+            Execution721MakerFeePacked::SIGNATURE_HASH => {
+                let event = log.log_decode::<Execution721MakerFeePacked>().ok()?;
+                let event = event.data();
+                decode_packed(
+                    event.tokenIdListingIndexTrader,
+                    event.collectionPriceSide,
+                    Some(event.makerFeeRecipientRate),
+                    collection,
+                    token_id,
+                )
+            }
+            _ => None,
+        })
+    }
+}
+
+fn decode_v1(log: &Log, collection: Address, token_id: U256) -> Option<Sale> {
+    let event = log.log_decode::<OrdersMatched>().ok()?;
+    let sell = &event.data().sell;
+    if sell.collection != collection || sell.tokenId != token_id {
+        return None;
+    }
+
+    let fees = sell
+        .fees
+        .iter()
+        .map(|fee| Fee {
+            recipient: Some(fee.recipient),
+            amount: sell.price * U256::from(fee.rate) / U256::from(RATE_BASE),
+        })
+        .collect();
+
+    Some(Sale {
+        marketplace: "blur",
+        price: sell.price,
+        currency: sell.paymentToken,
+        fees,
+    })
+}
+
+/// Decode the packed fields of the second version:
+/// `tokenId (88) | listingIndex (8) | trader (160)`, `side (8) | price (88) | collection (160)`
+/// and `rate (16) | recipient (160)` for the fee
+fn decode_packed(
+    token_id_listing_index_trader: U256,
+    collection_price_side: U256,
+    fee_recipient_rate: Option<U256>,
+    collection: Address,
+    token_id: U256,
+) -> Option<Sale> {
+    let packed_collection = low_address(collection_price_side);
+    let packed_token_id = token_id_listing_index_trader >> 168;
+    if packed_collection != collection || packed_token_id != token_id {
+        return None;
+    }
+
+    let price = (collection_price_side >> 160) & ((U256::from(1) << 88) - U256::from(1));
+    let side = collection_price_side >> 248_usize;
+    // Listings are paid in ETH, bids in the pool
+    let currency = if side.is_zero() {
+        Address::ZERO
+    } else {
+        BLUR_POOL
+    };
+
+    let fees = fee_recipient_rate
+        .map(|fee_recipient_rate| {
+            let rate = (fee_recipient_rate >> 160) & U256::from(u16::MAX);
+            Fee {
+                recipient: Some(low_address(fee_recipient_rate)),
+                amount: price * rate / U256::from(RATE_BASE),
+            }
+        })
+        .into_iter()
+        .collect();
+
+    Some(Sale {
+        marketplace: "blur",
+        price,
+        currency,
+        fees,
+    })
+}
+
+/// Address stored in the lowest 160 bits
+fn low_address(packed: U256) -> Address {
+    Address::from_slice(&packed.to_be_bytes::<32>()[12..])
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{b256, keccak256};
+
+    use super::*;
+    use crate::sales::tests::{raw_log, receipt_log};
+
+    const COLLECTION: Address = address!("ed5af388653567af2f388e6224dc7c4b3241c544");
+    const TRADER: Address = address!("1000000000000000000000000000000000000001");
+    const FEE_RECIPIENT: Address = address!("0000000000000000000000000000000000000fee");
+
+    /// A listing of token 4321 for 2 ETH with a 0.5% taker fee
+    fn taker_fee_listing() -> Execution721TakerFeePacked {
+        let price = U256::from(2_000_000_000_000_000_000_u64);
+        let address_bits = |address: Address| U256::from_be_slice(address.as_slice());
+
+        Execution721TakerFeePacked {
+            orderHash: b256!("b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2"),
+            tokenIdListingIndexTrader: (U256::from(4321) << 168) | address_bits(TRADER),
+            collectionPriceSide: (price << 160) | address_bits(COLLECTION),
+            takerFeeRecipientRate: (U256::from(50) << 160) | address_bits(FEE_RECIPIENT),
+        }
+    }
+
+    #[test]
+    fn decodes_packed_listing() {
+        let logs = [receipt_log(EXCHANGES[1], &taker_fee_listing())];
+
+        let sale = Blur.decode(&logs, COLLECTION, U256::from(4321)).unwrap();
+        assert_eq!(sale.price, U256::from(2_000_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, Address::ZERO);
+        assert_eq!(sale.fees.len(), 1);
+        assert_eq!(sale.fees[0].recipient, Some(FEE_RECIPIENT));
+        assert_eq!(sale.fees[0].amount, U256::from(10_000_000_000_000_000_u64));
+    }
+
+    /// Hand-encoded from the published ABI of Blur's second exchange version,
+    /// not recorded from a mainnet receipt
+    #[test]
+    fn decodes_raw_packed_bid() {
+        let topics = [keccak256(
+            "Execution721MakerFeePacked(bytes32,uint256,uint256,uint256)",
+        )];
+        let words = [
+            "b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b1c2",
+            // token 4321, listing index 0 and the trader
+            "00000000000000000010e1001000000000000000000000000000000000000001",
+            // bid side, 2 ETH and the collection
+            "010000001bc16d674ec80000ed5af388653567af2f388e6224dc7c4b3241c544",
+            // 50 basis points and the fee recipient
+            "0000000000000000000000320000000000000000000000000000000000000fee",
+        ];
+        let logs = [raw_log(EXCHANGES[1], &topics, &words)];
+
+        let sale = Blur.decode(&logs, COLLECTION, U256::from(4321)).unwrap();
+        assert_eq!(sale.price, U256::from(2_000_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, BLUR_POOL);
+        assert_eq!(sale.fees.len(), 1);
+        assert_eq!(sale.fees[0].recipient, Some(FEE_RECIPIENT));
+        assert_eq!(sale.fees[0].amount, U256::from(10_000_000_000_000_000_u64));
+    }
+
+    #[test]
+    fn ignores_spoofed_emitter() {
+        let spoofer = address!("3000000000000000000000000000000000000003");
+        let logs = [receipt_log(spoofer, &taker_fee_listing())];
+
+        assert!(Blur.decode(&logs, COLLECTION, U256::from(4321)).is_none());
+    }
+}
@@ -0,0 +1,288 @@
+use alloy::{
+    primitives::{address, Address, U256},
+    rpc::types::Log,
+    sol_types::SolEvent,
+};
+
+use super::{emitted_by, Fee, Sale, SaleDecoder};
+use crate::interfaces::{LooksRare as V1, LooksRareV2 as V2};
+
+/// Exchanges of the first and the second version
+const EXCHANGES: &[Address] = &[
+    address!("59728544B08AB483533076417FbBB2fD0B17CE3a"),
+    address!("0000000000E655fAe4d56241588680F86E3b2377"),
+];
+
+/// LooksRare exchange, both versions
+pub(super) struct LooksRare;
+
+impl SaleDecoder for LooksRare {
+    fn decode(&self, logs: &[Log], collection: Address, token_id: U256) -> Option<Sale> {
+        emitted_by(logs, EXCHANGES).find_map(|log| match *log.topic0()? {
+            V1::TakerAsk::SIGNATURE_HASH => {
+                let event = log.log_decode::<V1::TakerAsk>().ok()?;
+                let event = event.data();
+                decode_v1(
+                    logs,
+                    (event.collection, event.tokenId),
+                    event.currency,
+                    event.price,
+                    collection,
+                    token_id,
+                )
+            }
+            V1::TakerBid::SIGNATURE_HASH => {
+                let event = log.log_decode::<V1::TakerBid>().ok()?;
+                let event = event.data();
+                decode_v1(
+                    logs,
+                    (event.collection, event.tokenId),
+                    event.currency,
+                    event.price,
+                    collection,
+                    token_id,
+                )
+            }
+            V2::TakerAsk::SIGNATURE_HASH => {
+                let event = log.log_decode::<V2::TakerAsk>().ok()?;
+                let event = event.data();
+                decode_v2(
+                    (event.collection, &event.itemIds),
+                    event.currency,
+                    event.feeRecipients,
+                    event.feeAmounts,
+                    collection,
+                    token_id,
+                )
+            }
+            V2::TakerBid::SIGNATURE_HASH => {
+                let event = log.log_decode::<V2::TakerBid>().ok()?;
+                let event = event.data();
+                decode_v2(
+                    (event.collection, &event.itemIds),
+                    event.currency,
+                    event.feeRecipients,
+                    event.feeAmounts,
+                    collection,
+                    token_id,
+                )
+            }
+            _ => None,
+        })
+    }
+}
+
+/// The first version only reports royalties, through a separate event
+fn decode_v1(
+    logs: &[Log],
+    (sold_collection, sold_token_id): (Address, U256),
+    currency: Address,
+    price: U256,
+    collection: Address,
+    token_id: U256,
+) -> Option<Sale> {
+    if sold_collection != collection || sold_token_id != token_id {
+        return None;
+    }
+
+    let fees = emitted_by(logs, EXCHANGES)
+        .filter(|log| log.topic0() == Some(&V1::RoyaltyPayment::SIGNATURE_HASH))
+        .filter_map(|log| log.log_decode::<V1::RoyaltyPayment>().ok())
+        .filter(|event| event.data().collection == collection && event.data().tokenId == token_id)
+        .map(|event| Fee {
+            recipient: Some(event.data().royaltyRecipient),
+            amount: event.data().amount,
+        })
+        .collect();
+
+    Some(Sale {
+        marketplace: "looksrare",
+        price,
+        currency,
+        fees,
+    })
+}
+
+/// `feeAmounts` holds the seller's proceeds, the creator fee and the protocol fee,
+/// `feeRecipients` the seller and the creator
+fn decode_v2(
+    (sold_collection, item_ids): (Address, &[U256]),
+    currency: Address,
+    fee_recipients: [Address; 2],
+    fee_amounts: [U256; 3],
+    collection: Address,
+    token_id: U256,
+) -> Option<Sale> {
+    if sold_collection != collection || !item_ids.contains(&token_id) {
+        return None;
+    }
+
+    let fees = [
+        Fee {
+            recipient: Some(fee_recipients[1]),
+            amount: fee_amounts[1],
+        },
+        Fee {
+            recipient: None,
+            amount: fee_amounts[2],
+        },
+    ]
+    .into_iter()
+    .filter(|fee| !fee.amount.is_zero())
+    .collect();
+
+    Some(Sale {
+        marketplace: "looksrare",
+        price: fee_amounts.iter().sum(),
+        currency,
+        fees,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{b256, keccak256};
+
+    use super::*;
+    use crate::sales::tests::{raw_log, receipt_log};
+
+    const COLLECTION: Address = address!("60e4d786628fea6478f785a6d7e704777c86a7c6");
+    const WETH: Address = address!("c02aaa39b223fe8d0a0e5c4f27ead9083c756cc2");
+    const CREATOR: Address = address!("1000000000000000000000000000000000000001");
+    const SPOOFER: Address = address!("3000000000000000000000000000000000000003");
+
+    fn taker_ask() -> V1::TakerAsk {
+        V1::TakerAsk {
+            orderHash: b256!("c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2a3b4c5d6e7f8a9b0c1d2"),
+            orderNonce: U256::from(7),
+            taker: address!("2000000000000000000000000000000000000002"),
+            maker: address!("4000000000000000000000000000000000000004"),
+            strategy: address!("5000000000000000000000000000000000000005"),
+            currency: WETH,
+            collection: COLLECTION,
+            tokenId: U256::from(99),
+            amount: U256::from(1),
+            price: U256::from(500_000_000_000_000_000_u64),
+        }
+    }
+
+    fn royalty(amount: u64) -> V1::RoyaltyPayment {
+        V1::RoyaltyPayment {
+            collection: COLLECTION,
+            tokenId: U256::from(99),
+            royaltyRecipient: CREATOR,
+            currency: WETH,
+            amount: U256::from(amount),
+        }
+    }
+
+    #[test]
+    fn decodes_v1_accepted_offer_with_royalty() {
+        let logs = [
+            receipt_log(EXCHANGES[0], &royalty(12_500_000_000_000_000)),
+            // Royalties reported by other contracts are not part of the sale
+            receipt_log(SPOOFER, &royalty(400_000_000_000_000_000)),
+            receipt_log(EXCHANGES[0], &taker_ask()),
+        ];
+
+        let sale = LooksRare.decode(&logs, COLLECTION, U256::from(99)).unwrap();
+        assert_eq!(sale.price, U256::from(500_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, WETH);
+        assert_eq!(sale.fees.len(), 1);
+        assert_eq!(sale.fees[0].recipient, Some(CREATOR));
+        assert_eq!(sale.fees[0].amount, U256::from(12_500_000_000_000_000_u64));
+    }
+
+    #[test]
+    fn decodes_v2_listing() {
+        let taker_bid = V2::TakerBid {
+            nonceInvalidationParameters: V2::NonceInvalidationParameters {
+                orderHash: b256!(
+                    "d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2"
+                ),
+                orderNonce: U256::from(3),
+                isNonceInvalidated: true,
+            },
+            bidUser: address!("2000000000000000000000000000000000000002"),
+            bidRecipient: address!("2000000000000000000000000000000000000002"),
+            strategyId: U256::ZERO,
+            currency: Address::ZERO,
+            collection: COLLECTION,
+            itemIds: vec![U256::from(99)],
+            amounts: vec![U256::from(1)],
+            feeRecipients: [
+                address!("4000000000000000000000000000000000000004"),
+                CREATOR,
+            ],
+            feeAmounts: [
+                U256::from(975_000_000_000_000_000_u64),
+                U256::from(20_000_000_000_000_000_u64),
+                U256::from(5_000_000_000_000_000_u64),
+            ],
+        };
+        let logs = [receipt_log(EXCHANGES[1], &taker_bid)];
+
+        let sale = LooksRare.decode(&logs, COLLECTION, U256::from(99)).unwrap();
+        assert_eq!(sale.price, U256::from(1_000_000_000_000_000_000_u64));
+        assert_eq!(sale.fees.len(), 2);
+        assert_eq!(sale.fees[0].recipient, Some(CREATOR));
+        assert_eq!(sale.fees[1].recipient, None);
+    }
+
+    /// Hand-encoded from the published ABI of LooksRare's second version,
+    /// not recorded from a mainnet receipt
+    #[test]
+    fn decodes_raw_v2_listing() {
+        let topics = [keccak256(
+            "TakerBid((bytes32,uint256,bool),address,address,uint256,address,address,\
+             uint256[],uint256[],address[2],uint256[3])",
+        )];
+        let words = [
+            // nonceInvalidationParameters
+            "d1e2f3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b3c4d5e6f7a8b9c0d1e2",
+            "0000000000000000000000000000000000000000000000000000000000000003",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            // bidUser, bidRecipient, strategyId, currency and collection
+            "0000000000000000000000002000000000000000000000000000000000000002",
+            "0000000000000000000000002000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "00000000000000000000000060e4d786628fea6478f785a6d7e704777c86a7c6",
+            // offsets of itemIds and amounts
+            "00000000000000000000000000000000000000000000000000000000000001e0",
+            "0000000000000000000000000000000000000000000000000000000000000220",
+            // feeRecipients: the seller and the creator
+            "0000000000000000000000004000000000000000000000000000000000000004",
+            "0000000000000000000000001000000000000000000000000000000000000001",
+            // feeAmounts: 0.975 ETH to the seller, 0.02 ETH of royalties and a 0.005 ETH protocol fee
+            "0000000000000000000000000000000000000000000000000d87e55590018000",
+            "00000000000000000000000000000000000000000000000000470de4df820000",
+            "0000000000000000000000000000000000000000000000000011c37937e08000",
+            // itemIds: token 99
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000063",
+            // amounts
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+        ];
+        let logs = [raw_log(EXCHANGES[1], &topics, &words)];
+
+        let sale = LooksRare.decode(&logs, COLLECTION, U256::from(99)).unwrap();
+        assert_eq!(sale.price, U256::from(1_000_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, Address::ZERO);
+        assert_eq!(sale.fees.len(), 2);
+        assert_eq!(sale.fees[0].recipient, Some(CREATOR));
+        assert_eq!(sale.fees[0].amount, U256::from(20_000_000_000_000_000_u64));
+        assert_eq!(sale.fees[1].recipient, None);
+        assert_eq!(sale.fees[1].amount, U256::from(5_000_000_000_000_000_u64));
+    }
+
+    #[test]
+    fn ignores_spoofed_emitter() {
+        let logs = [receipt_log(SPOOFER, &taker_ask())];
+
+        assert!(LooksRare
+            .decode(&logs, COLLECTION, U256::from(99))
+            .is_none());
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use alloy::{
+    primitives::{Address, B256, U256},
+    providers::Provider,
+    rpc::types::Log,
+};
+use serde::Serialize;
+
+use crate::state::ChainState;
+
+mod blur;
+mod looksrare;
+mod seaport;
+mod x2y2;
+
+/// Number of transaction receipts remembered per chain
+const CACHED_RECEIPTS: usize = 64;
+
+/// Marketplaces whose sales are recognized
+const DECODERS: &[&dyn SaleDecoder] = &[
+    &seaport::Seaport,
+    &blur::Blur,
+    &looksrare::LooksRare,
+    &x2y2::X2y2,
+];
+
+/// Sale of a token decoded from the marketplace events of its transaction
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Sale {
+    pub(crate) marketplace: &'static str,
+    /// Amount paid by the buyer for the whole order, in the smallest unit of `currency`
+    pub(crate) price: U256,
+    /// Zero address for the native currency
+    pub(crate) currency: Address,
+    pub(crate) fees: Vec<Fee>,
+}
+
+/// Part of the price paid to someone else than the seller, e.g. royalties and protocol fees
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Fee {
+    /// `None` when the marketplace doesn't report it
+    pub(crate) recipient: Option<Address>,
+    pub(crate) amount: U256,
+}
+
+/// Decodes the sales of a marketplace from the logs of a transaction
+pub(crate) trait SaleDecoder: Send + Sync {
+    /// Find the sale of the token `token_id` of `collection` among the logs of its transaction
+    fn decode(&self, logs: &[Log], collection: Address, token_id: U256) -> Option<Sale>;
+}
+
+/// Logs emitted by one of `exchanges`, any other contract of the transaction could fake their events
+fn emitted_by<'a>(logs: &'a [Log], exchanges: &'a [Address]) -> impl Iterator<Item = &'a Log> {
+    logs.iter().filter(|log| exchanges.contains(&log.address()))
+}
+
+/// Find the marketplace sale of a token in the logs of its transaction
+pub(crate) fn find_sale(logs: &[Log], collection: Address, token_id: U256) -> Option<Sale> {
+    DECODERS
+        .iter()
+        .find_map(|decoder| decoder.decode(logs, collection, token_id))
+}
+
+/// Logs of recently fetched transaction receipts,
+/// so the transfers of a transaction (e.g. a sweep) share one lookup
+#[derive(Debug, Default)]
+pub(crate) struct ReceiptCache {
+    receipts: Mutex<VecDeque<(B256, Arc<Vec<Log>>)>>,
+}
+
+impl ReceiptCache {
+    /// Logs of the transaction `hash`, `None` when its receipt cannot be fetched
+    pub(crate) async fn logs(&self, chain_state: &ChainState, hash: B256) -> Option<Arc<Vec<Log>>> {
+        let cached = self
+            .receipts
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(cached, _)| *cached == hash)
+            .map(|(_, logs)| Arc::clone(logs));
+        if let Some(logs) = cached {
+            return Some(logs);
+        }
+
+        let receipt = chain_state
+            .provider
+            .get_transaction_receipt(hash)
+            .await
+            .ok()??;
+        let logs = Arc::new(receipt.inner.logs().to_vec());
+
+        let mut receipts = self.receipts.lock().unwrap();
+        receipts.push_back((hash, Arc::clone(&logs)));
+        while receipts.len() > CACHED_RECEIPTS {
+            receipts.pop_front();
+        }
+
+        Some(logs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        hex,
+        primitives::{b256, LogData},
+        sol_types::SolEvent,
+    };
+
+    use super::*;
+
+    /// Log of `event` emitted by `address`, as found in a transaction receipt
+    pub(super) fn receipt_log(address: Address, event: &impl SolEvent) -> Log {
+        log(address, event.encode_log_data())
+    }
+
+    /// Log emitted by `address` from its raw topics and data, written as 32-byte hex words.
+    /// Unlike `receipt_log`, it doesn't go through the bindings, so it catches a wrong ABI.
+    pub(super) fn raw_log(address: Address, topics: &[B256], words: &[&str]) -> Log {
+        let data = words
+            .iter()
+            .flat_map(|word| {
+                assert_eq!(word.len(), 64, "{word} is not a 32-byte word");
+                hex::decode(word).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        log(
+            address,
+            LogData::new_unchecked(topics.to_vec(), data.into()),
+        )
+    }
+
+    fn log(address: Address, data: LogData) -> Log {
+        Log {
+            inner: alloy::primitives::Log { address, data },
+            block_number: Some(17_000_000),
+            transaction_hash: Some(b256!(
+                "5f1c4a0e2a4e4d6f6f0e6bb7d8c3f2b1a0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5"
+            )),
+            log_index: Some(0),
+            ..Default::default()
+        }
+    }
+}
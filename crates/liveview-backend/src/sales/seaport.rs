@@ -0,0 +1,231 @@
+use alloy::{
+    primitives::{address, Address, U256},
+    rpc::types::Log,
+    sol_types::SolEvent,
+};
+
+use super::{emitted_by, Fee, Sale, SaleDecoder};
+use crate::interfaces::Seaport::OrderFulfilled;
+
+/// Seaport 1.1, 1.4, 1.5 and 1.6, deployed at the same addresses on every chain
+const EXCHANGES: &[Address] = &[
+    address!("00000000006c3852cbEf3e08E8dF289169EdE581"),
+    address!("00000000000001ad428e4906aE43D8F9852d0dD6"),
+    address!("00000000000000ADc04C56Bf30aC9d3c0aAF14dC"),
+    address!("0000000000000068F116a894984e2DB1123eB395"),
+];
+/// Item types paid with, native currency and ERC20
+const NATIVE: u8 = 0;
+const ERC20: u8 = 1;
+
+/// Seaport, used by OpenSea and many other marketplaces
+pub(super) struct Seaport;
+
+impl SaleDecoder for Seaport {
+    fn decode(&self, logs: &[Log], collection: Address, token_id: U256) -> Option<Sale> {
+        emitted_by(logs, EXCHANGES)
+            .filter(|log| log.topic0() == Some(&OrderFulfilled::SIGNATURE_HASH))
+            .filter_map(|log| log.log_decode::<OrderFulfilled>().ok())
+            .find_map(|event| decode_order(event.data(), collection, token_id))
+    }
+}
+
+fn is_payment(item_type: u8) -> bool {
+    item_type == NATIVE || item_type == ERC20
+}
+
+fn decode_order(order: &OrderFulfilled, collection: Address, token_id: U256) -> Option<Sale> {
+    let is_token = |item_type: u8, token: Address, identifier: U256| {
+        !is_payment(item_type) && token == collection && identifier == token_id
+    };
+
+    let payments = order
+        .consideration
+        .iter()
+        .filter(|item| is_payment(item.itemType))
+        .collect::<Vec<_>>();
+
+    if order
+        .offer
+        .iter()
+        .any(|item| is_token(item.itemType, item.token, item.identifier))
+    {
+        // A listing, the buyer pays every consideration item and the offerer keeps its share
+        let currency = payments.first()?.token;
+        let price = payments
+            .iter()
+            .filter(|item| item.token == currency)
+            .map(|item| item.amount)
+            .sum();
+        let fees = payments
+            .iter()
+            .filter(|item| item.token == currency && item.recipient != order.offerer)
+            .map(|item| Fee {
+                recipient: Some(item.recipient),
+                amount: item.amount,
+            })
+            .collect();
+
+        return Some(Sale {
+            marketplace: "seaport",
+            price,
+            currency,
+            fees,
+        });
+    }
+
+    if order
+        .consideration
+        .iter()
+        .any(|item| is_token(item.itemType, item.token, item.identifier))
+    {
+        // An accepted offer, the offerer pays and the fees are taken out of its payment
+        let offered = order
+            .offer
+            .iter()
+            .filter(|item| is_payment(item.itemType))
+            .collect::<Vec<_>>();
+        let currency = offered.first()?.token;
+        let price = offered
+            .iter()
+            .filter(|item| item.token == currency)
+            .map(|item| item.amount)
+            .sum();
+        let fees = payments
+            .iter()
+            .filter(|item| item.token == currency)
+            .map(|item| Fee {
+                recipient: Some(item.recipient),
+                amount: item.amount,
+            })
+            .collect();
+
+        return Some(Sale {
+            marketplace: "seaport",
+            price,
+            currency,
+            fees,
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{b256, keccak256};
+
+    use super::*;
+    use crate::{
+        interfaces::Seaport::{ReceivedItem, SpentItem},
+        sales::tests::{raw_log, receipt_log},
+    };
+
+    const COLLECTION: Address = address!("bc4ca0eda7647a8ab7c2061c2e118a18a936f13d");
+    const SELLER: Address = address!("1000000000000000000000000000000000000001");
+    const BUYER: Address = address!("2000000000000000000000000000000000000002");
+    const FEE_RECIPIENT: Address = address!("0000a26b00c1f0df003000390027140000faa719");
+
+    /// A listing of token 1234 for 1 ETH, 2.5% of which go to the fee recipient
+    fn listing() -> OrderFulfilled {
+        let payment = |amount: u64, recipient: Address| ReceivedItem {
+            itemType: NATIVE,
+            token: Address::ZERO,
+            identifier: U256::ZERO,
+            amount: U256::from(amount),
+            recipient,
+        };
+
+        OrderFulfilled {
+            orderHash: b256!("a3c1a0b6f2d4e5c6b7a8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0"),
+            offerer: SELLER,
+            zone: Address::ZERO,
+            recipient: BUYER,
+            offer: vec![SpentItem {
+                itemType: 2,
+                token: COLLECTION,
+                identifier: U256::from(1234),
+                amount: U256::from(1),
+            }],
+            consideration: vec![
+                payment(975_000_000_000_000_000, SELLER),
+                payment(25_000_000_000_000_000, FEE_RECIPIENT),
+            ],
+        }
+    }
+
+    #[test]
+    fn decodes_listing() {
+        let logs = [receipt_log(EXCHANGES[3], &listing())];
+
+        let sale = Seaport.decode(&logs, COLLECTION, U256::from(1234)).unwrap();
+        assert_eq!(sale.price, U256::from(1_000_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, Address::ZERO);
+        assert_eq!(sale.fees.len(), 1);
+        assert_eq!(sale.fees[0].recipient, Some(FEE_RECIPIENT));
+        assert_eq!(sale.fees[0].amount, U256::from(25_000_000_000_000_000_u64));
+    }
+
+    /// Hand-encoded from the published ABI of Seaport 1.5, not recorded from a mainnet receipt
+    #[test]
+    fn decodes_raw_listing() {
+        let topics = [
+            keccak256(
+                "OrderFulfilled(bytes32,address,address,address,(uint8,address,uint256,uint256)[],\
+                 (uint8,address,uint256,uint256,address)[])",
+            ),
+            SELLER.into_word(),
+            Address::ZERO.into_word(),
+        ];
+        let words = [
+            // orderHash, recipient and the offsets of offer and consideration
+            "a3c1a0b6f2d4e5c6b7a8d9e0f1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0",
+            "0000000000000000000000002000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000080",
+            "0000000000000000000000000000000000000000000000000000000000000120",
+            // offer: token 1234 of the collection
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "000000000000000000000000bc4ca0eda7647a8ab7c2061c2e118a18a936f13d",
+            "00000000000000000000000000000000000000000000000000000000000004d2",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            // consideration: 0.975 ETH to the seller and 0.025 ETH to the fee recipient
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000d87e55590018000",
+            "0000000000000000000000001000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000058d15e17628000",
+            "0000000000000000000000000000a26b00c1f0df003000390027140000faa719",
+        ];
+        let logs = [raw_log(EXCHANGES[3], &topics, &words)];
+
+        let sale = Seaport.decode(&logs, COLLECTION, U256::from(1234)).unwrap();
+        assert_eq!(sale.price, U256::from(1_000_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, Address::ZERO);
+        assert_eq!(sale.fees.len(), 1);
+        assert_eq!(sale.fees[0].recipient, Some(FEE_RECIPIENT));
+        assert_eq!(sale.fees[0].amount, U256::from(25_000_000_000_000_000_u64));
+    }
+
+    #[test]
+    fn ignores_other_tokens() {
+        let logs = [receipt_log(EXCHANGES[3], &listing())];
+
+        assert!(Seaport.decode(&logs, COLLECTION, U256::from(1)).is_none());
+    }
+
+    #[test]
+    fn ignores_spoofed_emitter() {
+        let spoofer = address!("3000000000000000000000000000000000000003");
+        let logs = [receipt_log(spoofer, &listing())];
+
+        assert!(Seaport
+            .decode(&logs, COLLECTION, U256::from(1234))
+            .is_none());
+    }
+}
@@ -0,0 +1,195 @@
+use alloy::{
+    primitives::{address, Address, U256},
+    rpc::types::Log,
+    sol_types::{SolEvent, SolValue},
+};
+
+use super::{emitted_by, Fee, Sale, SaleDecoder};
+use crate::interfaces::X2Y2::{EvInventory, Pair};
+
+const EXCHANGE: Address = address!("74312363e45DCaBA76c59ec49a7Aa8A65a67EeD3");
+/// Fee percentages are in millionths
+const RATE_BASE: u64 = 1_000_000;
+
+/// X2Y2 exchange
+pub(super) struct X2y2;
+
+impl SaleDecoder for X2y2 {
+    fn decode(&self, logs: &[Log], collection: Address, token_id: U256) -> Option<Sale> {
+        emitted_by(logs, &[EXCHANGE])
+            .filter(|log| log.topic0() == Some(&EvInventory::SIGNATURE_HASH))
+            .filter_map(|log| log.log_decode::<EvInventory>().ok())
+            .find_map(|event| decode_inventory(event.data(), collection, token_id))
+    }
+}
+
+fn decode_inventory(event: &EvInventory, collection: Address, token_id: U256) -> Option<Sale> {
+    // The sold tokens are ABI encoded in the order item
+    let pairs = Vec::<Pair>::abi_decode(&event.item.data, false).ok()?;
+    if !pairs
+        .iter()
+        .any(|pair| pair.token == collection && pair.tokenId == token_id)
+    {
+        return None;
+    }
+
+    let price = event.detail.price;
+    let fees = event
+        .detail
+        .fees
+        .iter()
+        .map(|fee| Fee {
+            recipient: Some(fee.to),
+            amount: price * fee.percentage / U256::from(RATE_BASE),
+        })
+        .collect();
+
+    Some(Sale {
+        marketplace: "x2y2",
+        price,
+        currency: event.currency,
+        fees,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{b256, keccak256, Bytes};
+
+    use super::*;
+    use crate::{
+        interfaces::X2Y2::{Fee as X2y2Fee, OrderItem, SettleDetail},
+        sales::tests::{raw_log, receipt_log},
+    };
+
+    const COLLECTION: Address = address!("8a90cab2b38dba80c64b7734e58ee1db38b8992e");
+    const FEE_RECIPIENT: Address = address!("d823c605807cc5e6bd6fc0d7e4eea50d3e2d66cd");
+
+    /// A listing of token 77 for 0.8 ETH with a 0.5% protocol fee
+    fn inventory() -> EvInventory {
+        let pairs = vec![Pair {
+            token: COLLECTION,
+            tokenId: U256::from(77),
+        }];
+        let price = U256::from(800_000_000_000_000_000_u64);
+
+        EvInventory {
+            itemHash: b256!("e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2"),
+            maker: address!("1000000000000000000000000000000000000001"),
+            taker: address!("2000000000000000000000000000000000000002"),
+            orderSalt: U256::from(1),
+            settleSalt: U256::from(2),
+            intent: U256::from(1),
+            delegateType: U256::from(1),
+            deadline: U256::from(1_700_000_000),
+            currency: Address::ZERO,
+            dataMask: Bytes::new(),
+            item: OrderItem {
+                price,
+                data: pairs.abi_encode().into(),
+            },
+            detail: SettleDetail {
+                op: 1,
+                orderIdx: U256::ZERO,
+                itemIdx: U256::ZERO,
+                price,
+                itemHash: b256!("e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2"),
+                executionDelegate: address!("f849de01b080adc3a814fabe1e2087475cf2e354"),
+                dataReplacement: Bytes::new(),
+                bidIncentivePct: U256::ZERO,
+                aucMinIncrementPct: U256::ZERO,
+                aucIncDurationSecs: U256::ZERO,
+                fees: vec![X2y2Fee {
+                    percentage: U256::from(5_000),
+                    to: FEE_RECIPIENT,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn decodes_listing() {
+        let logs = [receipt_log(EXCHANGE, &inventory())];
+
+        let sale = X2y2.decode(&logs, COLLECTION, U256::from(77)).unwrap();
+        assert_eq!(sale.price, U256::from(800_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, Address::ZERO);
+        assert_eq!(sale.fees.len(), 1);
+        assert_eq!(sale.fees[0].recipient, Some(FEE_RECIPIENT));
+        assert_eq!(sale.fees[0].amount, U256::from(4_000_000_000_000_000_u64));
+    }
+
+    /// Hand-encoded from the published ABI of the X2Y2 exchange, not recorded from a mainnet receipt
+    #[test]
+    fn decodes_raw_listing() {
+        let topics = [
+            keccak256(
+                "EvInventory(bytes32,address,address,uint256,uint256,uint256,uint256,uint256,\
+                 address,bytes,(uint256,bytes),(uint8,uint256,uint256,uint256,bytes32,address,\
+                 bytes,uint256,uint256,uint256,(uint256,address)[]))",
+            ),
+            b256!("e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2"),
+        ];
+        let words = [
+            // maker, taker, orderSalt, settleSalt, intent, delegateType, deadline and currency
+            "0000000000000000000000001000000000000000000000000000000000000001",
+            "0000000000000000000000002000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "000000000000000000000000000000000000000000000000000000006553f100",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            // offsets of dataMask, item and detail
+            "0000000000000000000000000000000000000000000000000000000000000160",
+            "0000000000000000000000000000000000000000000000000000000000000180",
+            "0000000000000000000000000000000000000000000000000000000000000260",
+            // dataMask: empty
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            // item: 0.8 ETH and its data, the ABI encoded pairs with token 77 of the collection
+            "0000000000000000000000000000000000000000000000000b1a2bc2ec500000",
+            "0000000000000000000000000000000000000000000000000000000000000040",
+            "0000000000000000000000000000000000000000000000000000000000000080",
+            "0000000000000000000000000000000000000000000000000000000000000020",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000008a90cab2b38dba80c64b7734e58ee1db38b8992e",
+            "000000000000000000000000000000000000000000000000000000000000004d",
+            // detail: op, orderIdx, itemIdx, price, itemHash and executionDelegate
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000b1a2bc2ec500000",
+            "e1f2a3b4c5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d0e1f2",
+            "000000000000000000000000f849de01b080adc3a814fabe1e2087475cf2e354",
+            // offset of dataReplacement, bidIncentivePct, aucMinIncrementPct,
+            // aucIncDurationSecs and the offset of fees
+            "0000000000000000000000000000000000000000000000000000000000000160",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000180",
+            // dataReplacement: empty
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            // fees: 0.5% to the fee recipient
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000001388",
+            "000000000000000000000000d823c605807cc5e6bd6fc0d7e4eea50d3e2d66cd",
+        ];
+        let logs = [raw_log(EXCHANGE, &topics, &words)];
+
+        let sale = X2y2.decode(&logs, COLLECTION, U256::from(77)).unwrap();
+        assert_eq!(sale.price, U256::from(800_000_000_000_000_000_u64));
+        assert_eq!(sale.currency, Address::ZERO);
+        assert_eq!(sale.fees.len(), 1);
+        assert_eq!(sale.fees[0].recipient, Some(FEE_RECIPIENT));
+        assert_eq!(sale.fees[0].amount, U256::from(4_000_000_000_000_000_u64));
+    }
+
+    #[test]
+    fn ignores_spoofed_emitter() {
+        let spoofer = address!("3000000000000000000000000000000000000003");
+        let logs = [receipt_log(spoofer, &inventory())];
+
+        assert!(X2y2.decode(&logs, COLLECTION, U256::from(77)).is_none());
+    }
+}
@@ -1,25 +1,138 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use alloy::{primitives::Address, providers::RootProvider, transports::BoxTransport};
 
+use crate::{
+    blocks::{BlockTracker, Confirmation},
+    cache::Caches,
+    logs::LogSource,
+    resolver::Resolver,
+    rpc::Endpoints,
+    sales::ReceiptCache,
+    store::Store,
+    subscriptions::SubscriptionManager,
+    tokens::PendingValidations,
+};
+
 #[derive(Debug, Clone)]
 pub(crate) struct ChainState {
+    pub(crate) name: String,
+    pub(crate) chain_id: u64,
     pub(crate) multicall_address: Address,
+    /// Provider failing over between the chain's endpoints
     pub(crate) provider: Arc<RootProvider<BoxTransport>>,
+    pub(crate) endpoints: Arc<Endpoints>,
+    pub(crate) log_source: LogSource,
+    /// Used when polling, including as a fallback when subscribing fails
+    pub(crate) poll_interval: Duration,
+    pub(crate) subscriptions: Arc<SubscriptionManager>,
+    /// Gateways resolving IPFS and Arweave metadata, shared by all chains
+    pub(crate) resolver: Arc<Resolver>,
+    /// Token and collection caches, shared by all chains
+    pub(crate) caches: Arc<Caches>,
+    /// Persistent copy of the caches, if enabled
+    pub(crate) store: Option<Arc<Store>>,
+    /// Receipts looked up to decode the sales of transfers
+    pub(crate) receipts: Arc<ReceiptCache>,
+    /// Validations of contracts in progress, shared by concurrent lookups
+    pub(crate) pending_validations: Arc<PendingValidations>,
+    pub(crate) blocks: Arc<BlockTracker>,
+    /// Default confirmation policy of the chain's subscriptions
+    pub(crate) confirmation: Confirmation,
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct AppState {
-    // pub(crate) mainnet: Arc<ChainState>,
-    // pub(crate) base: Arc<ChainState>,
-    // pub(crate) arbitrum: Arc<ChainState>,
-    // pub(crate) optimism: Arc<ChainState>,
-    // pub(crate) polygon: Arc<ChainState>,
-    // pub(crate) bsc: Arc<ChainState>,
-    pub(crate) mainnet: ChainState,
-    pub(crate) base: ChainState,
-    pub(crate) arbitrum: ChainState,
-    pub(crate) optimism: ChainState,
-    pub(crate) polygon: ChainState,
-    pub(crate) bsc: ChainState,
+    /// Configured chains keyed by name
+    pub(crate) chains: HashMap<String, Arc<ChainState>>,
+    /// Chain ids mapped to chain names
+    pub(crate) chain_ids: HashMap<u64, String>,
+    /// Maximum number of concurrent subscriptions per socket
+    pub(crate) max_subscriptions: usize,
+    pub(crate) caches: Arc<Caches>,
+}
+
+impl AppState {
+    pub(crate) fn new(
+        chains: Vec<ChainState>,
+        max_subscriptions: usize,
+        caches: Arc<Caches>,
+    ) -> Self {
+        let chain_ids = chains
+            .iter()
+            .map(|chain| (chain.chain_id, chain.name.to_owned()))
+            .collect();
+        let chains = chains
+            .into_iter()
+            .map(|chain| (chain.name.to_owned(), Arc::new(chain)))
+            .collect();
+
+        Self {
+            chains,
+            chain_ids,
+            max_subscriptions,
+            caches,
+        }
+    }
+
+    /// Look up a chain either by its name or by its decimal chain id
+    pub(crate) fn chain(&self, key: &str) -> Result<Arc<ChainState>, UnknownChain> {
+        let name = match key.parse::<u64>() {
+            Ok(chain_id) => self.chain_ids.get(&chain_id).map(String::as_str),
+            Err(_) => Some(key),
+        };
+
+        name.and_then(|name| self.chains.get(name))
+            .map(Arc::clone)
+            .ok_or_else(|| UnknownChain(key.to_owned()))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct UnknownChain(pub(crate) String);
+
+impl std::fmt::Display for UnknownChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown chain: {}", self.0)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use alloy::{providers::ProviderBuilder, rpc::client::RpcClient, transports::Transport};
+
+    use super::*;
+    use crate::{data, rpc::FailoverTransport};
+
+    /// Chain whose only RPC endpoint refuses connections, so every call fails right away
+    pub(crate) fn unreachable_chain_state() -> ChainState {
+        let endpoints = Arc::new(Endpoints::new([("http://127.0.0.1:1".parse().unwrap(), 0)]));
+        let provider = ProviderBuilder::new().on_client(RpcClient::new(
+            FailoverTransport::new(Arc::clone(&endpoints)).boxed(),
+            true,
+        ));
+        let poll_interval = Duration::from_secs(2);
+
+        ChainState {
+            name: "test".to_owned(),
+            chain_id: 1,
+            multicall_address: Address::ZERO,
+            provider: Arc::new(provider),
+            endpoints,
+            log_source: LogSource::Poll {
+                interval: poll_interval,
+            },
+            poll_interval,
+            subscriptions: Arc::default(),
+            resolver: Arc::new(
+                Resolver::new(data::Gateways::default(), &data::Fetcher::default()).unwrap(),
+            ),
+            caches: Arc::new(Caches::new(&data::Cache::default())),
+            store: None,
+            receipts: Arc::default(),
+            pending_validations: Arc::default(),
+            blocks: Arc::new(BlockTracker::new("test".to_owned())),
+            confirmation: Confirmation::default(),
+        }
+    }
 }
@@ -0,0 +1,438 @@
+use std::{
+    cmp::Reverse,
+    hash::Hash,
+    io,
+    ops::RangeInclusive,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use alloy::primitives::{Address, U256};
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    cache::{Caches, TtlCache},
+    data,
+    metadata::TokenInfo,
+    tokens::TokenData,
+};
+
+/// A stored value and the time it was looked up
+#[derive(Serialize, Deserialize)]
+struct Record<T> {
+    /// Unix timestamp in seconds
+    stored_at: i64,
+    value: T,
+}
+
+/// Time a record was stored at, read without decoding its value
+#[derive(Deserialize)]
+struct Stamp {
+    stored_at: i64,
+}
+
+/// A tree pruned to its `capacity` once it grows past it by an eighth
+#[derive(Debug)]
+struct BoundedTree {
+    tree: sled::Tree,
+    capacity: usize,
+    /// Number of entries, `sled::Tree::len` walks the whole tree
+    len: AtomicUsize,
+}
+
+impl BoundedTree {
+    fn open(db: &sled::Db, name: &str, capacity: usize) -> sled::Result<Self> {
+        let tree = db.open_tree(name)?;
+
+        Ok(Self {
+            len: AtomicUsize::new(tree.len()),
+            tree,
+            capacity,
+        })
+    }
+
+    fn put<T: Serialize>(&self, key: &[u8], value: &T) {
+        let record = Record {
+            stored_at: Utc::now().timestamp(),
+            value,
+        };
+        let bytes = match serde_json::to_vec(&record) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                warn!(?err, "Failed to serialize cache entry");
+                return;
+            }
+        };
+
+        match self.tree.insert(key, bytes) {
+            Ok(None) => {
+                let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+                if len > self.capacity + self.capacity / 8 {
+                    self.prune();
+                }
+            }
+            Ok(Some(_)) => {}
+            Err(err) => warn!(?err, "Failed to store cache entry"),
+        }
+    }
+
+    fn remove(&self, key: impl AsRef<[u8]>) {
+        match self.tree.remove(key) {
+            Ok(Some(_)) => {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+            }
+            Ok(None) => {}
+            Err(err) => warn!(?err, "Failed to remove stored cache entry"),
+        }
+    }
+
+    fn remove_range(&self, range: RangeInclusive<Vec<u8>>) {
+        for entry in self.tree.range(range) {
+            match entry {
+                Ok((key, _)) => self.remove(key),
+                Err(err) => warn!(?err, "Failed to read stored entry"),
+            }
+        }
+    }
+
+    /// Remove the oldest entries until at most `capacity` are left
+    fn prune(&self) {
+        let mut entries = self
+            .tree
+            .iter()
+            .filter_map(|entry| {
+                let (key, value) = entry.ok()?;
+                // Entries which can't be read go first
+                let stored_at = serde_json::from_slice::<Stamp>(&value)
+                    .map_or(i64::MIN, |stamp| stamp.stored_at);
+                Some((stored_at, key))
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(stored_at, _)| Reverse(*stored_at));
+        for (_, key) in entries.into_iter().skip(self.capacity) {
+            self.remove(key);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Trees {
+    /// `TokenData` keyed by chain id and contract
+    collections: BoundedTree,
+    /// `TokenInfo` keyed by chain id, contract and token id
+    tokens: BoundedTree,
+}
+
+/// Change of the store, applied by its writer in the order it was requested
+enum Write {
+    Collection {
+        key: Vec<u8>,
+        token_data: TokenData,
+    },
+    Token {
+        key: Vec<u8>,
+        info: Arc<TokenInfo>,
+    },
+    RemoveTokens(RangeInclusive<Vec<u8>>),
+    /// Acknowledged once the previous writes are applied
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+impl Trees {
+    fn apply(&self, write: Write) {
+        match write {
+            Write::Collection { key, token_data } => self.collections.put(&key, &token_data),
+            Write::Token { key, info } => self.tokens.put(&key, info.as_ref()),
+            Write::RemoveTokens(range) => self.tokens.remove_range(range),
+            #[cfg(test)]
+            Write::Flush(done) => {
+                done.send(()).ok();
+            }
+        }
+    }
+}
+
+/// Persistent copy of the caches, used to warm them after a restart.
+///
+/// Writes are queued to a dedicated thread, so that disk access and pruning
+/// never block the async runtime, and are applied in order.
+#[derive(Debug)]
+pub(crate) struct Store {
+    trees: Arc<Trees>,
+    writes: mpsc::Sender<Write>,
+}
+
+impl Store {
+    /// Open the store, holding at most as many entries as the caches
+    pub(crate) fn open(path: &Path, config: &data::Cache) -> io::Result<Self> {
+        let db = sled::open(path)?;
+        let trees = Arc::new(Trees {
+            collections: BoundedTree::open(&db, "collections", config.collection_capacity)?,
+            tokens: BoundedTree::open(&db, "tokens", config.token_capacity)?,
+        });
+
+        // Stops once the store is dropped
+        let (writes, queued) = mpsc::channel();
+        let writer = Arc::clone(&trees);
+        thread::Builder::new()
+            .name("store-writer".to_owned())
+            .spawn(move || {
+                for write in queued {
+                    writer.apply(write);
+                }
+            })?;
+
+        Ok(Self { trees, writes })
+    }
+
+    fn queue(&self, write: Write) {
+        if self.writes.send(write).is_err() {
+            warn!("Store writer stopped, dropping the write");
+        }
+    }
+
+    pub(crate) fn put_collection(&self, chain_id: u64, address: Address, token_data: &TokenData) {
+        self.queue(Write::Collection {
+            key: collection_key(chain_id, address),
+            token_data: token_data.clone(),
+        });
+    }
+
+    pub(crate) fn put_token(
+        &self,
+        chain_id: u64,
+        address: Address,
+        token_id: U256,
+        info: &Arc<TokenInfo>,
+    ) {
+        self.queue(Write::Token {
+            key: token_key(chain_id, address, token_id),
+            info: Arc::clone(info),
+        });
+    }
+
+    /// Remove the tokens `token_ids` of `address`
+    pub(crate) fn remove_tokens(
+        &self,
+        chain_id: u64,
+        address: Address,
+        token_ids: &RangeInclusive<U256>,
+    ) {
+        if token_ids.start() > token_ids.end() {
+            return;
+        }
+
+        // Keys are big-endian, so the stored tokens of the range are contiguous
+        let start = token_key(chain_id, address, *token_ids.start());
+        let end = token_key(chain_id, address, *token_ids.end());
+        self.queue(Write::RemoveTokens(start..=end));
+    }
+
+    /// Wait until the queued writes are applied
+    #[cfg(test)]
+    pub(crate) fn flush(&self) {
+        let (done, applied) = mpsc::channel();
+        self.queue(Write::Flush(done));
+        applied.recv().ok();
+    }
+
+    /// Load the stored entries into the caches and drop the expired ones.
+    /// Reads the whole store, so it runs on a blocking thread.
+    ///
+    /// Returns the number of collections and tokens loaded.
+    pub(crate) fn warm(&self, caches: &Caches) -> (usize, usize) {
+        let collections = warm_cache(
+            &self.trees.collections,
+            &caches.collections,
+            decode_collection_key,
+            |token_data: TokenData| token_data,
+        );
+        let tokens = warm_cache(
+            &self.trees.tokens,
+            &caches.tokens,
+            decode_token_key,
+            |info: TokenInfo| Arc::new(info),
+        );
+
+        (collections, tokens)
+    }
+}
+
+/// Insert the most recent entries of `tree` which haven't expired into `cache`,
+/// and drop the others from the tree
+fn warm_cache<K, V, T>(
+    tree: &BoundedTree,
+    cache: &TtlCache<K, V>,
+    decode_key: impl Fn(&[u8]) -> Option<K>,
+    into_value: impl Fn(T) -> V,
+) -> usize
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    T: DeserializeOwned,
+{
+    // Leftovers of a run with a larger capacity
+    tree.prune();
+
+    let now = Utc::now().timestamp();
+
+    let mut records = Vec::new();
+    for entry in tree.tree.iter() {
+        let Ok((key, value)) = entry else {
+            continue;
+        };
+        let record = serde_json::from_slice::<Record<T>>(&value).ok();
+        let age = record.as_ref().map(|record| {
+            Duration::from_secs(u64::try_from(now - record.stored_at).unwrap_or_default())
+        });
+
+        match (decode_key(&key), record, age) {
+            (Some(decoded), Some(record), Some(age)) if age < cache.ttl() => {
+                records.push((key, decoded, record.value, age));
+            }
+            // Expired, or written by an incompatible version
+            _ => tree.remove(key),
+        }
+    }
+
+    records.sort_by_key(|(_, _, _, age)| *age);
+    for (key, ..) in records.drain(cache.capacity().min(records.len())..) {
+        tree.remove(key);
+    }
+
+    records
+        .into_iter()
+        .map(|(_, key, value, age)| cache.insert_aged(key, into_value(value), age))
+        .filter(|inserted| *inserted)
+        .count()
+}
+
+fn collection_key(chain_id: u64, address: Address) -> Vec<u8> {
+    let mut key = chain_id.to_be_bytes().to_vec();
+    key.extend_from_slice(address.as_slice());
+    key
+}
+
+fn token_key(chain_id: u64, address: Address, token_id: U256) -> Vec<u8> {
+    let mut key = collection_key(chain_id, address);
+    key.extend_from_slice(&token_id.to_be_bytes::<32>());
+    key
+}
+
+fn decode_collection_key(key: &[u8]) -> Option<(u64, Address)> {
+    if key.len() != 28 {
+        return None;
+    }
+
+    let chain_id = u64::from_be_bytes(key[..8].try_into().ok()?);
+    Some((chain_id, Address::from_slice(&key[8..28])))
+}
+
+fn decode_token_key(key: &[u8]) -> Option<(u64, Address, U256)> {
+    if key.len() != 60 {
+        return None;
+    }
+
+    let (chain_id, address) = decode_collection_key(&key[..28])?;
+    Some((chain_id, address, U256::from_be_slice(&key[28..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::metadata::ResolvedMetadata;
+
+    use super::*;
+
+    const COLLECTION: Address = Address::repeat_byte(0x11);
+    const OTHER_COLLECTION: Address = Address::repeat_byte(0x22);
+
+    /// The directory is removed once dropped, so it's kept along the store
+    fn open(token_capacity: usize) -> (TempDir, Store) {
+        let dir = TempDir::new().unwrap();
+        let config = data::Cache {
+            token_capacity,
+            ..Default::default()
+        };
+        let store = Store::open(dir.path(), &config).unwrap();
+
+        (dir, store)
+    }
+
+    fn info(token_id: u64) -> Arc<TokenInfo> {
+        Arc::new(TokenInfo {
+            token_uri: format!("ipfs://cid/{token_id}"),
+            resolved: ResolvedMetadata {
+                raw: None,
+                metadata: None,
+                image: None,
+                image_type: None,
+                image_media_type: None,
+            },
+        })
+    }
+
+    fn stored_tokens(store: &Store) -> Vec<(Address, U256)> {
+        store.flush();
+        store
+            .trees
+            .tokens
+            .tree
+            .iter()
+            .filter_map(|entry| decode_token_key(&entry.ok()?.0))
+            .map(|(_, address, token_id)| (address, token_id))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_at_most_an_eighth_more_than_the_capacity() {
+        let (_dir, store) = open(16);
+        for token_id in 0..100 {
+            store.put_token(1, COLLECTION, U256::from(token_id), &info(token_id));
+        }
+
+        let stored = stored_tokens(&store).len();
+        let len = store.trees.tokens.len.load(Ordering::Relaxed);
+        assert!(len <= 18, "{len} tokens stored");
+        assert_eq!(len, stored);
+    }
+
+    #[test]
+    fn removes_only_the_updated_tokens() {
+        let (_dir, store) = open(100);
+        for token_id in 0..10 {
+            store.put_token(1, COLLECTION, U256::from(token_id), &info(token_id));
+            store.put_token(1, OTHER_COLLECTION, U256::from(token_id), &info(token_id));
+        }
+
+        store.remove_tokens(1, COLLECTION, &(U256::from(3)..=U256::from(6)));
+
+        let stored = stored_tokens(&store);
+        assert_eq!(stored.len(), 16);
+        assert!(stored
+            .iter()
+            .all(|(address, token_id)| *address == OTHER_COLLECTION
+                || !(U256::from(3)..=U256::from(6)).contains(token_id)));
+        assert_eq!(store.trees.tokens.len.load(Ordering::Relaxed), 16);
+    }
+
+    #[test]
+    fn applies_writes_in_order() {
+        let (_dir, store) = open(100);
+        let token_ids = U256::from(1)..=U256::from(1);
+        store.put_token(1, COLLECTION, U256::from(1), &info(1));
+        store.remove_tokens(1, COLLECTION, &token_ids);
+        store.put_token(1, COLLECTION, U256::from(2), &info(2));
+
+        assert_eq!(stored_tokens(&store), [(COLLECTION, U256::from(2))]);
+    }
+}
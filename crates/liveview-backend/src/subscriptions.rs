@@ -0,0 +1,788 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::RangeInclusive,
+    sync::{Arc, Mutex},
+};
+
+use alloy::{
+    primitives::{utils::format_units, Address, FixedBytes, B256, U256},
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+    transports::TransportError,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{future::join_all, stream::BoxStream, StreamExt};
+use serde::Serialize;
+use tokio::{sync::broadcast, task::AbortHandle};
+use tracing::debug;
+
+use crate::{
+    interfaces::{ERC1155, ERC20, ERC721},
+    logs::{self, LogEvent, LogStream, StreamStatus},
+    metadata::{self, TokenMetadata},
+    positions::{self, Position, PositionEvent},
+    sales::{self, Sale},
+    state::ChainState,
+    tokens::{TokenData, TokenStandard},
+    utils::MetadataType,
+};
+
+/// Events buffered per feed for subscribers which fall behind
+const FEED_CAPACITY: usize = 1024;
+/// Number of blocks after which an event is no longer expected to be reverted
+const REORG_DEPTH: u64 = 128;
+/// Upper bound of the events remembered per feed for reverting
+const MAX_RECENT_EVENTS: usize = 1024;
+/// Tokens resolved again per metadata update, larger batches are only invalidated
+const MAX_REFRESHED_TOKENS: u64 = 100;
+
+/// A decoded and enriched Transfer, shared by every subscriber of the contract
+#[derive(Debug, Serialize)]
+pub(crate) struct Transfer {
+    pub(crate) address: Address,
+    pub(crate) standard: TokenStandard,
+    pub(crate) name: String,
+    pub(crate) symbol: String,
+    pub(crate) from: Address,
+    pub(crate) to: Address,
+    /// Not set for ERC20
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) token_id: Option<U256>,
+    /// Raw transferred value, always 1 for ERC721
+    pub(crate) amount: U256,
+    /// `amount` adjusted by the decimals of an ERC20
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) formatted_amount: Option<String>,
+    pub(crate) image: Option<String>,
+    pub(crate) image_type: Option<MetadataType>,
+    /// Media type of inline images, e.g. `image/svg+xml`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) image_media_type: Option<String>,
+    /// Normalized token metadata, not set for ERC20
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<TokenMetadata>,
+    /// Why the metadata of the token could not be fetched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata_error: Option<String>,
+    /// Marketplace sale the Transfer is part of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) sale: Option<Sale>,
+    /// Liquidity position of the token, only set when tracking positions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) position: Option<Position>,
+    pub(crate) block_number: u64,
+    pub(crate) block_hash: FixedBytes<32>,
+    pub(crate) transaction_hash: FixedBytes<32>,
+    pub(crate) log_index: u64,
+    /// Position of the token in an ERC1155 `TransferBatch`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) batch_index: Option<usize>,
+    /// Time of the Transfer's block, `None` if it could not be resolved
+    pub(crate) timestamp: Option<DateTime<Utc>>,
+    /// Time the server received the Transfer
+    pub(crate) received_at: DateTime<Utc>,
+}
+
+/// New metadata of a token announced as changed
+#[derive(Debug, Serialize)]
+pub(crate) struct UpdatedToken {
+    pub(crate) token_id: U256,
+    pub(crate) image: Option<String>,
+    pub(crate) image_type: Option<MetadataType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) image_media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) metadata: Option<TokenMetadata>,
+}
+
+/// An ERC4906 `MetadataUpdate` or `BatchMetadataUpdate`, with the metadata resolved again
+#[derive(Debug, Serialize)]
+pub(crate) struct MetadataUpdate {
+    pub(crate) address: Address,
+    pub(crate) standard: TokenStandard,
+    pub(crate) name: String,
+    pub(crate) symbol: String,
+    /// Equal to `to_token_id` for a `MetadataUpdate`
+    pub(crate) from_token_id: U256,
+    pub(crate) to_token_id: U256,
+    /// Tokens whose metadata could be fetched, empty for batches of more than `MAX_REFRESHED_TOKENS`
+    pub(crate) tokens: Vec<UpdatedToken>,
+    pub(crate) block_number: u64,
+    pub(crate) block_hash: FixedBytes<32>,
+    pub(crate) transaction_hash: FixedBytes<32>,
+    pub(crate) log_index: u64,
+    pub(crate) timestamp: Option<DateTime<Utc>>,
+    pub(crate) received_at: DateTime<Utc>,
+}
+
+/// A previously sent event whose block is no longer part of the chain
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub(crate) struct Revert {
+    pub(crate) address: Address,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) token_id: Option<U256>,
+    pub(crate) block_number: u64,
+    pub(crate) block_hash: FixedBytes<32>,
+    pub(crate) transaction_hash: FixedBytes<32>,
+    pub(crate) log_index: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) batch_index: Option<usize>,
+}
+
+impl From<&Transfer> for Revert {
+    fn from(transfer: &Transfer) -> Self {
+        Self {
+            address: transfer.address,
+            token_id: transfer.token_id,
+            block_number: transfer.block_number,
+            block_hash: transfer.block_hash,
+            transaction_hash: transfer.transaction_hash,
+            log_index: transfer.log_index,
+            batch_index: transfer.batch_index,
+        }
+    }
+}
+
+impl From<&PositionEvent> for Revert {
+    fn from(event: &PositionEvent) -> Self {
+        Self {
+            address: event.address,
+            token_id: Some(event.token_id),
+            block_number: event.block_number,
+            block_hash: event.block_hash,
+            transaction_hash: event.transaction_hash,
+            log_index: event.log_index,
+            batch_index: None,
+        }
+    }
+}
+
+impl From<&MetadataUpdate> for Revert {
+    fn from(update: &MetadataUpdate) -> Self {
+        Self {
+            address: update.address,
+            token_id: (update.from_token_id == update.to_token_id).then_some(update.from_token_id),
+            block_number: update.block_number,
+            block_hash: update.block_hash,
+            transaction_hash: update.transaction_hash,
+            log_index: update.log_index,
+            batch_index: None,
+        }
+    }
+}
+
+/// An event of a token, sent to subscribers once confirmed
+#[derive(Debug, Clone)]
+pub(crate) enum TokenEvent {
+    Transfer(Arc<Transfer>),
+    Position(Arc<PositionEvent>),
+    MetadataUpdate(Arc<MetadataUpdate>),
+}
+
+impl TokenEvent {
+    pub(crate) fn block_number(&self) -> u64 {
+        match self {
+            TokenEvent::Transfer(transfer) => transfer.block_number,
+            TokenEvent::Position(event) => event.block_number,
+            TokenEvent::MetadataUpdate(update) => update.block_number,
+        }
+    }
+
+    /// Revert of the event, should its block be orphaned
+    pub(crate) fn revert(&self) -> Revert {
+        match self {
+            TokenEvent::Transfer(transfer) => Revert::from(transfer.as_ref()),
+            TokenEvent::Position(event) => Revert::from(event.as_ref()),
+            TokenEvent::MetadataUpdate(update) => Revert::from(update.as_ref()),
+        }
+    }
+}
+
+/// Events of a contract a feed streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum FeedKind {
+    Transfers,
+    /// Transfers and liquidity changes of position manager NFTs, enriched with their positions
+    Positions,
+}
+
+impl FeedKind {
+    /// Signatures of the events of a contract implementing `standard`
+    pub(crate) fn events(self, standard: TokenStandard) -> Vec<B256> {
+        let mut events = standard.transfer_events();
+        if standard != TokenStandard::Erc20 {
+            events.extend(metadata::update_events());
+        }
+        if self == FeedKind::Positions {
+            events.extend(positions::position_events());
+        }
+        events
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum FeedEvent {
+    Token(TokenEvent),
+    Revert(Arc<Revert>),
+    Status {
+        address: Address,
+        status: StreamStatus,
+    },
+    /// The subscriber fell behind the feed and missed `skipped` events
+    Lagged {
+        address: Address,
+        skipped: u64,
+    },
+}
+
+#[derive(Debug)]
+struct Feed {
+    tx: broadcast::Sender<FeedEvent>,
+    /// Number of live `FeedHandle`s
+    refs: usize,
+    task: AbortHandle,
+}
+
+/// Keeps one upstream log stream per watched contract and feed kind of a chain
+/// and fans its events out to every interested socket
+#[derive(Debug, Default)]
+pub(crate) struct SubscriptionManager {
+    feeds: Mutex<HashMap<(Address, FeedKind), Feed>>,
+}
+
+impl SubscriptionManager {
+    /// Start receiving the events of `address`, opening the upstream stream if nobody watches it yet
+    pub(crate) async fn watch(
+        self: &Arc<Self>,
+        chain_state: &ChainState,
+        address: Address,
+        token_data: TokenData,
+        kind: FeedKind,
+    ) -> Result<FeedHandle, TransportError> {
+        let key = (address, kind);
+        if let Some(handle) = self.join(key) {
+            return Ok(handle);
+        }
+
+        let filter = Filter::new()
+            .address(address)
+            .event_signature(kind.events(token_data.standard));
+        let stream = logs::stream_logs(chain_state, filter).await?;
+
+        let mut feeds = self.feeds.lock().unwrap();
+        // Another socket may have opened the feed meanwhile, the new stream is dropped then
+        if let Some(feed) = feeds.get_mut(&key) {
+            feed.refs += 1;
+            return Ok(self.handle(key, feed.tx.subscribe()));
+        }
+
+        debug!(chain = chain_state.name, ?address, ?kind, "Opening feed");
+        let (tx, rx) = broadcast::channel(FEED_CAPACITY);
+        let task = tokio::spawn(run_feed(
+            chain_state.clone(),
+            address,
+            token_data,
+            kind,
+            stream,
+            tx.clone(),
+        ));
+        feeds.insert(
+            key,
+            Feed {
+                tx,
+                refs: 1,
+                task: task.abort_handle(),
+            },
+        );
+
+        Ok(self.handle(key, rx))
+    }
+
+    fn join(self: &Arc<Self>, key: (Address, FeedKind)) -> Option<FeedHandle> {
+        let mut feeds = self.feeds.lock().unwrap();
+        let feed = feeds.get_mut(&key)?;
+        feed.refs += 1;
+
+        Some(self.handle(key, feed.tx.subscribe()))
+    }
+
+    fn handle(
+        self: &Arc<Self>,
+        key: (Address, FeedKind),
+        rx: broadcast::Receiver<FeedEvent>,
+    ) -> FeedHandle {
+        FeedHandle {
+            manager: Arc::clone(self),
+            key,
+            rx: Some(rx),
+        }
+    }
+
+    fn release(&self, key: (Address, FeedKind)) {
+        let mut feeds = self.feeds.lock().unwrap();
+        let Some(feed) = feeds.get_mut(&key) else {
+            return;
+        };
+
+        feed.refs -= 1;
+        if feed.refs == 0 {
+            debug!(address = ?key.0, kind = ?key.1, "Closing feed");
+            feed.task.abort();
+            feeds.remove(&key);
+        }
+    }
+}
+
+/// Interest of one subscriber in a feed, released on drop
+#[derive(Debug)]
+pub(crate) struct FeedHandle {
+    manager: Arc<SubscriptionManager>,
+    key: (Address, FeedKind),
+    rx: Option<broadcast::Receiver<FeedEvent>>,
+}
+
+impl FeedHandle {
+    /// Turn the handle into a stream of the feed's events which keeps the interest alive
+    pub(crate) fn into_stream(mut self) -> BoxStream<'static, FeedEvent> {
+        let rx = self.rx.take().expect("receiver is only taken once");
+
+        futures_util::stream::unfold((rx, self), |(mut rx, handle)| async move {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => FeedEvent::Lagged {
+                    address: handle.key.0,
+                    skipped,
+                },
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+            Some((event, (rx, handle)))
+        })
+        .boxed()
+    }
+}
+
+impl Drop for FeedHandle {
+    fn drop(&mut self) {
+        self.manager.release(self.key);
+    }
+}
+
+/// Decode and enrich the logs of a contract once and broadcast them to its subscribers
+async fn run_feed(
+    chain_state: ChainState,
+    address: Address,
+    token_data: TokenData,
+    kind: FeedKind,
+    mut stream: LogStream,
+    tx: broadcast::Sender<FeedEvent>,
+) {
+    // Events which may still be reverted by a reorg
+    let mut recent = VecDeque::<Revert>::new();
+
+    while let Some(event) = stream.next().await {
+        let log = match event {
+            LogEvent::Log(log) => log,
+            LogEvent::Status(status) => {
+                tx.send(FeedEvent::Status { address, status }).ok();
+                continue;
+            }
+            LogEvent::Reorg(reorg) => {
+                let mut kept = VecDeque::with_capacity(recent.len());
+                for revert in recent.drain(..) {
+                    let orphaned = revert.block_number > reorg.fork_block
+                        && chain_state
+                            .blocks
+                            .is_canonical(
+                                &chain_state.provider,
+                                revert.block_number,
+                                revert.block_hash,
+                            )
+                            .await
+                            == Some(false);
+
+                    if orphaned {
+                        tx.send(FeedEvent::Revert(Arc::new(revert))).ok();
+                    } else {
+                        kept.push_back(revert);
+                    }
+                }
+                recent = kept;
+                continue;
+            }
+        };
+
+        if log.removed {
+            // Every token of a batch shares the log
+            let (removed, kept) = recent.drain(..).partition::<Vec<_>, _>(|revert| {
+                Some(revert.block_hash) == log.block_hash && Some(revert.log_index) == log.log_index
+            });
+            recent = kept.into();
+            for revert in removed {
+                tx.send(FeedEvent::Revert(Arc::new(revert))).ok();
+            }
+            continue;
+        }
+
+        // Logs after the detected fork are fetched again, including the ones still canonical
+        let is_sent = recent.iter().any(|revert| {
+            Some(revert.block_hash) == log.block_hash && Some(revert.log_index) == log.log_index
+        });
+        if is_sent {
+            continue;
+        }
+
+        for event in decode_log(&chain_state, &token_data, kind, &log).await {
+            let block_number = event.block_number();
+
+            recent.push_back(event.revert());
+            while recent.front().is_some_and(|oldest| {
+                oldest.block_number + REORG_DEPTH < block_number || recent.len() > MAX_RECENT_EVENTS
+            }) {
+                recent.pop_front();
+            }
+
+            tx.send(FeedEvent::Token(event)).ok();
+        }
+    }
+}
+
+/// Decode a log of a feed of `kind` into the events to send
+pub(crate) async fn decode_log(
+    chain_state: &ChainState,
+    token_data: &TokenData,
+    kind: FeedKind,
+    log: &Log,
+) -> Vec<TokenEvent> {
+    if let Some(token_ids) = metadata::updated_tokens(log) {
+        metadata::invalidate(chain_state, log.address(), &token_ids);
+        let update = decode_metadata_update(chain_state, token_data, log, token_ids).await;
+        return vec![TokenEvent::MetadataUpdate(Arc::new(update))];
+    }
+
+    if kind == FeedKind::Positions
+        && log
+            .topic0()
+            .is_some_and(|topic| positions::position_events().contains(topic))
+    {
+        return positions::decode_position(chain_state, log)
+            .await
+            .map(|event| TokenEvent::Position(Arc::new(event)))
+            .into_iter()
+            .collect();
+    }
+
+    let mut transfers = decode_transfers(chain_state, token_data, log).await;
+    if kind == FeedKind::Positions {
+        for transfer in &mut transfers {
+            let Some(token_id) = transfer.token_id else {
+                continue;
+            };
+            transfer.position = positions::fetch_position(
+                chain_state,
+                transfer.address,
+                token_id,
+                log.block_number,
+            )
+            .await;
+        }
+    }
+
+    transfers
+        .into_iter()
+        .map(|transfer| TokenEvent::Transfer(Arc::new(transfer)))
+        .collect()
+}
+
+/// Resolve the metadata of the tokens `token_ids` again, after their cached entries were invalidated
+async fn decode_metadata_update(
+    chain_state: &ChainState,
+    token_data: &TokenData,
+    log: &Log,
+    token_ids: RangeInclusive<U256>,
+) -> MetadataUpdate {
+    let received_at = Utc::now();
+    let timestamp = block_time(chain_state, log).await;
+
+    // Reveals may announce whole collections at once, which are left to be fetched on demand
+    let (from_token_id, to_token_id) = token_ids.into_inner();
+    let is_refreshed = from_token_id <= to_token_id
+        && to_token_id - from_token_id < U256::from(MAX_REFRESHED_TOKENS);
+    let token_count = if is_refreshed {
+        (to_token_id - from_token_id).to::<u64>() + 1
+    } else {
+        0
+    };
+
+    let lookups = (0..token_count).map(|offset| async move {
+        let token_id = from_token_id + U256::from(offset);
+        let resolved = metadata::fetch_metadata(
+            chain_state,
+            token_data.standard,
+            log.address(),
+            token_id,
+            true,
+        )
+        .await
+        .ok()?;
+        Some(UpdatedToken {
+            token_id,
+            image: resolved.image,
+            image_type: resolved.image_type,
+            image_media_type: resolved.image_media_type,
+            metadata: resolved.metadata,
+        })
+    });
+    let tokens = join_all(lookups).await.into_iter().flatten().collect();
+
+    MetadataUpdate {
+        address: log.address(),
+        standard: token_data.standard,
+        name: token_data.name.to_owned(),
+        symbol: token_data.symbol.to_owned(),
+        from_token_id,
+        to_token_id,
+        tokens,
+        block_number: log.block_number.unwrap_or_default(),
+        block_hash: log.block_hash.unwrap_or_default(),
+        transaction_hash: log.transaction_hash.unwrap_or_default(),
+        log_index: log.log_index.unwrap_or_default(),
+        timestamp,
+        received_at,
+    }
+}
+
+/// A token moved by a transfer log
+struct TokenMove {
+    from: Address,
+    to: Address,
+    token_id: Option<U256>,
+    amount: U256,
+    batch_index: Option<usize>,
+}
+
+/// Decode the tokens moved by a log of the given standard
+fn decode_moves(standard: TokenStandard, log: &Log) -> Option<Vec<TokenMove>> {
+    match standard {
+        // ERC20 and ERC721 share the Transfer signature, but only ERC721 indexes the token id
+        TokenStandard::Erc20 if log.topics().len() == 3 => {
+            let event = log.log_decode::<ERC20::Transfer>().ok()?;
+            let event_data = event.data();
+            Some(vec![TokenMove {
+                from: event_data.from,
+                to: event_data.to,
+                token_id: None,
+                amount: event_data.value,
+                batch_index: None,
+            }])
+        }
+        TokenStandard::Erc721 if log.topics().len() == 4 => {
+            let event = log.log_decode::<ERC721::Transfer>().ok()?;
+            let event_data = event.data();
+            Some(vec![TokenMove {
+                from: event_data.from,
+                to: event_data.to,
+                token_id: Some(event_data.tokenId),
+                amount: U256::from(1),
+                batch_index: None,
+            }])
+        }
+        TokenStandard::Erc1155 => match log.topic0() {
+            Some(&ERC1155::TransferSingle::SIGNATURE_HASH) => {
+                let event = log.log_decode::<ERC1155::TransferSingle>().ok()?;
+                let event_data = event.data();
+                Some(vec![TokenMove {
+                    from: event_data.from,
+                    to: event_data.to,
+                    token_id: Some(event_data.id),
+                    amount: event_data.value,
+                    batch_index: None,
+                }])
+            }
+            Some(&ERC1155::TransferBatch::SIGNATURE_HASH) => {
+                let event = log.log_decode::<ERC1155::TransferBatch>().ok()?;
+                let event_data = event.data();
+                // Expand the batch into one Transfer per token
+                let moves = event_data
+                    .ids
+                    .iter()
+                    .zip(&event_data.values)
+                    .enumerate()
+                    .map(|(batch_index, (id, value))| TokenMove {
+                        from: event_data.from,
+                        to: event_data.to,
+                        token_id: Some(*id),
+                        amount: *value,
+                        batch_index: Some(batch_index),
+                    })
+                    .collect();
+                Some(moves)
+            }
+            _ => None,
+        },
+        TokenStandard::Erc20 | TokenStandard::Erc721 => None,
+    }
+}
+
+/// Decode a transfer log into one Transfer per moved token and enrich them with the tokens' metadata.
+///
+/// Tokens whose metadata cannot be fetched are skipped.
+pub(crate) async fn decode_transfers(
+    chain_state: &ChainState,
+    token_data: &TokenData,
+    log: &Log,
+) -> Vec<Transfer> {
+    let received_at = Utc::now();
+
+    let moves = match decode_moves(token_data.standard, log) {
+        Some(moves) => moves,
+        None => return vec![], // Skip if errors occurs while decoding the event
+    };
+
+    let timestamp = block_time(chain_state, log).await;
+
+    // Sales are looked for in the other logs of the transaction, mints are never sales
+    let is_sale_candidate = token_data.standard != TokenStandard::Erc20
+        && moves
+            .iter()
+            .any(|token_move| token_move.from != Address::ZERO);
+    let transaction_logs = match log.transaction_hash {
+        Some(transaction_hash) if is_sale_candidate => {
+            chain_state
+                .receipts
+                .logs(chain_state, transaction_hash)
+                .await
+        }
+        _ => None,
+    };
+
+    let mut transfers = Vec::with_capacity(moves.len());
+    for token_move in moves {
+        // The transfer is sent without its metadata when it can't be fetched
+        let mut metadata_error = None;
+        let (image, image_type, image_media_type, token_metadata) = match token_move.token_id {
+            Some(token_id) => {
+                let resolved = metadata::fetch_metadata(
+                    chain_state,
+                    token_data.standard,
+                    log.address(),
+                    token_id,
+                    false,
+                )
+                .await;
+                match resolved {
+                    Ok(resolved) => (
+                        resolved.image,
+                        resolved.image_type,
+                        resolved.image_media_type,
+                        resolved.metadata,
+                    ),
+                    Err(err) => {
+                        metadata_error = Some(err.to_string());
+                        (None, None, None, None)
+                    }
+                }
+            }
+            // Fungible tokens have no per token metadata
+            None => (None, None, None, None),
+        };
+        let sale = match (&transaction_logs, token_move.token_id) {
+            (Some(logs), Some(token_id)) => sales::find_sale(logs, log.address(), token_id),
+            _ => None,
+        };
+        let formatted_amount = token_data
+            .decimals
+            .and_then(|decimals| format_units(token_move.amount, decimals).ok());
+
+        transfers.push(Transfer {
+            address: log.address(),
+            standard: token_data.standard,
+            name: token_data.name.to_owned(),
+            symbol: token_data.symbol.to_owned(),
+            from: token_move.from,
+            to: token_move.to,
+            token_id: token_move.token_id,
+            amount: token_move.amount,
+            formatted_amount,
+            image,
+            image_type,
+            image_media_type,
+            metadata: token_metadata,
+            metadata_error,
+            sale,
+            position: None,
+            block_number: log.block_number.unwrap_or_default(),
+            block_hash: log.block_hash.unwrap_or_default(),
+            transaction_hash: log.transaction_hash.unwrap_or_default(),
+            log_index: log.log_index.unwrap_or_default(),
+            batch_index: token_move.batch_index,
+            timestamp,
+            received_at,
+        });
+    }
+
+    transfers
+}
+
+/// Time of the block of `log`, `None` if it could not be resolved
+pub(crate) async fn block_time(chain_state: &ChainState, log: &Log) -> Option<DateTime<Utc>> {
+    // Providers may include the block timestamp in the log, saving a header lookup
+    let block_timestamp = match (log.block_timestamp, log.block_number, log.block_hash) {
+        (Some(timestamp), _, _) => timestamp,
+        (None, Some(number), Some(hash)) => {
+            chain_state
+                .blocks
+                .timestamp(&chain_state.provider, number, hash)
+                .await?
+        }
+        _ => return None,
+    };
+
+    DateTime::from_timestamp(block_timestamp.try_into().ok()?, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{address, b256};
+
+    use super::*;
+    use crate::state::tests::unreachable_chain_state;
+
+    #[tokio::test]
+    async fn sends_transfers_whose_metadata_fails() {
+        let chain_state = unreachable_chain_state();
+        let token_data = TokenData {
+            standard: TokenStandard::Erc721,
+            name: "Collection".to_owned(),
+            symbol: "COL".to_owned(),
+            decimals: None,
+        };
+        let transfer = ERC721::Transfer {
+            from: address!("1000000000000000000000000000000000000001"),
+            to: address!("2000000000000000000000000000000000000002"),
+            tokenId: U256::from(7),
+        };
+        let log = Log {
+            inner: alloy::primitives::Log {
+                address: address!("bc4ca0eda7647a8ab7c2061c2e118a18a936f13d"),
+                data: transfer.encode_log_data(),
+            },
+            block_number: Some(100),
+            block_hash: Some(b256!(
+                "0101010101010101010101010101010101010101010101010101010101010101"
+            )),
+            block_timestamp: Some(1_700_000_000),
+            transaction_hash: Some(b256!(
+                "0202020202020202020202020202020202020202020202020202020202020202"
+            )),
+            log_index: Some(3),
+            ..Default::default()
+        };
+
+        let transfers = decode_transfers(&chain_state, &token_data, &log).await;
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].token_id, Some(U256::from(7)));
+        assert!(transfers[0].metadata.is_none());
+        assert!(transfers[0].image.is_none());
+        assert!(transfers[0].metadata_error.is_some());
+    }
+}
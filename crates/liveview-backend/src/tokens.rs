@@ -0,0 +1,544 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use alloy::{
+    primitives::{Address, FixedBytes, B256, U256},
+    providers::Provider,
+    sol_types::{SolCall, SolEvent},
+};
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use tracing::debug;
+
+use crate::{
+    interfaces::{Multicall, ERC1155, ERC20, ERC721},
+    state::ChainState,
+};
+
+pub(crate) const ERC721_INTERFACE_ID: FixedBytes<4> = FixedBytes([0x80, 0xac, 0x58, 0xcd]);
+pub(crate) const ERC1155_INTERFACE_ID: FixedBytes<4> = FixedBytes([0xd9, 0xb6, 0x7a, 0x26]);
+
+/// Token standard implemented by a contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TokenStandard {
+    Erc20,
+    Erc721,
+    Erc1155,
+}
+
+impl TokenStandard {
+    /// Signatures of the events moving tokens of this standard
+    pub(crate) fn transfer_events(self) -> Vec<B256> {
+        match self {
+            TokenStandard::Erc20 => vec![ERC20::Transfer::SIGNATURE_HASH],
+            TokenStandard::Erc721 => vec![ERC721::Transfer::SIGNATURE_HASH],
+            TokenStandard::Erc1155 => vec![
+                ERC1155::TransferSingle::SIGNATURE_HASH,
+                ERC1155::TransferBatch::SIGNATURE_HASH,
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TokenData {
+    pub(crate) standard: TokenStandard,
+    pub(crate) name: String,
+    pub(crate) symbol: String,
+    /// Only set for ERC20
+    pub(crate) decimals: Option<u8>,
+}
+
+/// Why an address cannot be watched
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InvalidReason {
+    NotAContract,
+    UnsupportedStandard,
+    MissingMetadata,
+    DecodeFailure,
+    NotPositionManager,
+    LookupFailed,
+}
+
+impl fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InvalidReason::NotAContract => "Address is not a contract",
+            InvalidReason::UnsupportedStandard => {
+                "Contract is neither an ERC20, ERC721 nor ERC1155 token"
+            }
+            InvalidReason::MissingMetadata => "Contract does not implement name and symbol",
+            InvalidReason::DecodeFailure => "Failed to decode the contract's name or symbol",
+            InvalidReason::NotPositionManager => {
+                "Positions are only tracked for position manager contracts"
+            }
+            InvalidReason::LookupFailed => "Failed to look up the contract",
+        })
+    }
+}
+
+/// Validation result of a single address
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Validation {
+    pub(crate) address: Address,
+    #[serde(flatten)]
+    pub(crate) status: ValidationStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum ValidationStatus {
+    Valid {
+        standard: TokenStandard,
+        name: String,
+        symbol: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        decimals: Option<u8>,
+    },
+    Invalid {
+        reason: InvalidReason,
+        message: String,
+    },
+}
+
+impl ValidationStatus {
+    pub(crate) fn invalid(reason: InvalidReason) -> Self {
+        ValidationStatus::Invalid {
+            reason,
+            message: reason.to_string(),
+        }
+    }
+}
+
+impl Validation {
+    pub(crate) fn valid(address: Address, token_data: TokenData) -> Self {
+        Self {
+            address,
+            status: ValidationStatus::Valid {
+                standard: token_data.standard,
+                name: token_data.name,
+                symbol: token_data.symbol,
+                decimals: token_data.decimals,
+            },
+        }
+    }
+
+    fn lookup_failed(address: Address) -> Self {
+        Self {
+            address,
+            status: ValidationStatus::invalid(InvalidReason::LookupFailed),
+        }
+    }
+
+    pub(crate) fn token_data(&self) -> Option<TokenData> {
+        match &self.status {
+            ValidationStatus::Valid {
+                standard,
+                name,
+                symbol,
+                decimals,
+            } => Some(TokenData {
+                standard: *standard,
+                name: name.to_owned(),
+                symbol: symbol.to_owned(),
+                decimals: *decimals,
+            }),
+            ValidationStatus::Invalid { .. } => None,
+        }
+    }
+}
+
+/// Check which addresses are ERC20, ERC721 or ERC1155 contracts and fetch their name and symbol
+pub(crate) async fn validate(
+    chain_state: &ChainState,
+    addresses: &[Address],
+) -> Result<Vec<Validation>, String> {
+    let multicall = Multicall::new(
+        chain_state.multicall_address,
+        Arc::clone(&chain_state.provider),
+    );
+
+    let mut calls = vec![];
+    for addr in addresses {
+        let erc721 = ERC721::new(addr.to_owned(), Arc::clone(&chain_state.provider));
+        let erc20 = ERC20::new(addr.to_owned(), Arc::clone(&chain_state.provider));
+
+        calls.push(Multicall::Call {
+            target: addr.to_owned(),
+            gasLimit: U256::MAX,
+            callData: erc721
+                .supportsInterface(ERC721_INTERFACE_ID)
+                .calldata()
+                .to_owned(),
+        });
+        calls.push(Multicall::Call {
+            target: addr.to_owned(),
+            gasLimit: U256::MAX,
+            callData: erc721
+                .supportsInterface(ERC1155_INTERFACE_ID)
+                .calldata()
+                .to_owned(),
+        });
+        calls.push(Multicall::Call {
+            target: addr.to_owned(),
+            gasLimit: U256::MAX,
+            callData: erc721.name().calldata().to_owned(),
+        });
+        calls.push(Multicall::Call {
+            target: addr.to_owned(),
+            gasLimit: U256::MAX,
+            callData: erc721.symbol().calldata().to_owned(),
+        });
+        calls.push(Multicall::Call {
+            target: addr.to_owned(),
+            gasLimit: U256::MAX,
+            callData: erc20.decimals().calldata().to_owned(),
+        });
+    }
+
+    // Check all addresses for support of ERC721.supportsInterface in multicall
+    let multicall_res = match multicall.multicall(calls).call().await {
+        Ok(res) => res.returnData,
+        Err(_) => return Err("Failed to call fetch data".to_owned()),
+    };
+
+    let mut validations = Vec::with_capacity(addresses.len());
+
+    for (address, res) in addresses.iter().zip(
+        multicall_res
+            /* 2 for supportsInterface, 1 for name, 1 for symbol, 1 for decimals */
+            .chunks(5),
+    ) {
+        // First indexes are for the supportsInterface calls, the last one for decimals
+        let supports = |res: &Multicall::Result| {
+            match ERC721::supportsInterfaceCall::abi_decode_returns(&res.returnData, false) {
+                Ok(res) => res._0,
+                Err(_) => false, // Error means that the address doesn't support the interface
+            }
+        };
+        let decimals = match ERC20::decimalsCall::abi_decode_returns(&res[4].returnData, true) {
+            Ok(decimals) if res[4].success => Some(decimals._0),
+            _ => None,
+        };
+        let standard = if supports(&res[0]) {
+            TokenStandard::Erc721
+        } else if supports(&res[1]) {
+            TokenStandard::Erc1155
+        } else if decimals.is_some() {
+            TokenStandard::Erc20
+        } else {
+            // Calls to accounts without code succeed with empty return data
+            let reason = if res[0].success && res[0].returnData.is_empty() {
+                match chain_state.provider.get_code_at(*address).await {
+                    Ok(code) if code.is_empty() => InvalidReason::NotAContract,
+                    _ => InvalidReason::UnsupportedStandard,
+                }
+            } else {
+                InvalidReason::UnsupportedStandard
+            };
+            validations.push(Validation {
+                address: *address,
+                status: ValidationStatus::invalid(reason),
+            });
+            continue;
+        };
+        let decimals = match standard {
+            TokenStandard::Erc20 => decimals,
+            TokenStandard::Erc721 | TokenStandard::Erc1155 => None,
+        };
+
+        // Third index in for name, fourth index in for symbol
+        let (name, symbol) = (&res[2], &res[3]);
+
+        // Name and symbol are optional for ERC1155
+        if standard == TokenStandard::Erc1155 {
+            let decode = |res: &Multicall::Result| {
+                if !res.success {
+                    return String::new();
+                }
+                decode_string(&res.returnData).unwrap_or_default()
+            };
+            validations.push(Validation {
+                address: *address,
+                status: ValidationStatus::Valid {
+                    standard,
+                    name: decode(name),
+                    symbol: decode(symbol),
+                    decimals,
+                },
+            });
+            continue;
+        }
+
+        if !name.success || !symbol.success {
+            validations.push(Validation {
+                address: *address,
+                status: ValidationStatus::invalid(InvalidReason::MissingMetadata),
+            });
+            continue;
+        }
+
+        let status = match (
+            decode_string(&name.returnData),
+            decode_string(&symbol.returnData),
+        ) {
+            (Some(name), Some(symbol)) => ValidationStatus::Valid {
+                standard,
+                name,
+                symbol,
+                decimals,
+            },
+            _ => ValidationStatus::invalid(InvalidReason::DecodeFailure),
+        };
+        validations.push(Validation {
+            address: *address,
+            status,
+        });
+    }
+
+    Ok(validations)
+}
+
+/// Validations in progress on a chain, so that concurrent lookups of an address share one
+#[derive(Debug, Default)]
+pub(crate) struct PendingValidations {
+    /// Filled with the validation once it is done, closed if its lookup was cancelled
+    pending: Mutex<HashMap<Address, watch::Receiver<Option<Validation>>>>,
+}
+
+/// Validations a lookup is responsible for, which are no longer pending once it is dropped
+struct Claim<'a> {
+    pending: &'a PendingValidations,
+    senders: HashMap<Address, watch::Sender<Option<Validation>>>,
+}
+
+impl Claim<'_> {
+    fn complete(&mut self, validation: &Validation) {
+        if let Some(sender) = self.senders.get(&validation.address) {
+            sender.send_replace(Some(validation.clone()));
+        }
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.pending.pending.lock().unwrap();
+        for address in self.senders.keys() {
+            pending.remove(address);
+        }
+    }
+}
+
+/// `validate` through the collection cache, which only keeps valid tokens.
+///
+/// The addresses missing from the cache are validated together, unless another lookup
+/// is already validating them, and each one whose lookup fails is reported as such.
+/// Returns a validation per address, duplicates included.
+pub(crate) async fn lookup(chain_state: &ChainState, addresses: &[Address]) -> Vec<Validation> {
+    let chain_id = chain_state.chain_id;
+
+    let mut validations = HashMap::with_capacity(addresses.len());
+    let mut claim = Claim {
+        pending: &chain_state.pending_validations,
+        senders: HashMap::new(),
+    };
+    let mut waiting = vec![];
+    let mut seen = HashSet::with_capacity(addresses.len());
+    for address in addresses {
+        if !seen.insert(*address) {
+            continue;
+        }
+        if let Some(token_data) = chain_state.caches.collections.get(&(chain_id, *address)) {
+            validations.insert(*address, Validation::valid(*address, token_data));
+            continue;
+        }
+
+        let mut pending = chain_state.pending_validations.pending.lock().unwrap();
+        match pending.get(address) {
+            Some(receiver) => waiting.push((*address, receiver.clone())),
+            None => {
+                let (sender, receiver) = watch::channel(None);
+                pending.insert(*address, receiver);
+                claim.senders.insert(*address, sender);
+            }
+        }
+    }
+
+    let misses = claim.senders.keys().copied().collect::<Vec<_>>();
+    let waits = waiting
+        .into_iter()
+        .map(|(address, mut receiver)| async move {
+            let validation = receiver
+                .wait_for(Option::is_some)
+                .await
+                .ok()
+                .and_then(|validation| validation.clone());
+            (address, validation)
+        });
+    let (fetched, waited) = futures_util::join!(fetch(chain_state, &misses), join_all(waits));
+
+    for validation in fetched {
+        if let Some(token_data) = validation.token_data() {
+            if let Some(store) = &chain_state.store {
+                store.put_collection(chain_id, validation.address, &token_data);
+            }
+            chain_state
+                .caches
+                .collections
+                .insert((chain_id, validation.address), token_data);
+        }
+        claim.complete(&validation);
+        validations.insert(validation.address, validation);
+    }
+    drop(claim);
+
+    // The lookups waited for were cancelled, so these addresses are validated here
+    let mut abandoned = vec![];
+    for (address, validation) in waited {
+        match validation {
+            Some(validation) => {
+                validations.insert(address, validation);
+            }
+            None => abandoned.push(address),
+        }
+    }
+    for validation in fetch(chain_state, &abandoned).await {
+        validations.insert(validation.address, validation);
+    }
+
+    addresses
+        .iter()
+        .filter_map(|address| validations.get(address).cloned())
+        .collect()
+}
+
+/// Validate the addresses together, then one by one if that fails
+async fn fetch(chain_state: &ChainState, addresses: &[Address]) -> Vec<Validation> {
+    if addresses.is_empty() {
+        return vec![];
+    }
+
+    match validate(chain_state, addresses).await {
+        Ok(fetched) => fetched,
+        // Retry one by one, so that a single address can't fail the others
+        Err(err) if addresses.len() > 1 => {
+            debug!(chain = chain_state.name, %err, "Failed to validate addresses together");
+            let lookups = addresses.iter().map(|address| async move {
+                match validate(chain_state, &[*address]).await {
+                    Ok(mut validation) => validation.remove(0),
+                    Err(_) => Validation::lookup_failed(*address),
+                }
+            });
+            join_all(lookups).await
+        }
+        Err(_) => addresses
+            .iter()
+            .map(|address| Validation::lookup_failed(*address))
+            .collect(),
+    }
+}
+
+/// Decode the return data of `name` or `symbol`,
+/// including older tokens like MKR which return a `bytes32` instead of a `string`
+pub(crate) fn decode_string(data: &[u8]) -> Option<String> {
+    if let Ok(res) = ERC20::nameCall::abi_decode_returns(data, false) {
+        return Some(res._0);
+    }
+
+    if data.len() != 32 {
+        return None;
+    }
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    String::from_utf8(data[..end].to_vec()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::address;
+
+    use super::*;
+    use crate::state::tests::unreachable_chain_state;
+
+    const ADDRESS: Address = address!("bc4ca0eda7647a8ab7c2061c2e118a18a936f13d");
+
+    fn is_valid(validation: &Validation) -> bool {
+        matches!(validation.status, ValidationStatus::Valid { .. })
+    }
+
+    #[tokio::test]
+    async fn shares_pending_validations() {
+        let chain_state = unreachable_chain_state();
+        let (sender, receiver) = watch::channel(None);
+        chain_state
+            .pending_validations
+            .pending
+            .lock()
+            .unwrap()
+            .insert(ADDRESS, receiver);
+
+        // The RPC is unreachable, so a valid token can only come from the pending validation
+        let token_data = TokenData {
+            standard: TokenStandard::Erc721,
+            name: "Collection".to_owned(),
+            symbol: "COL".to_owned(),
+            decimals: None,
+        };
+        let (validations, ()) =
+            futures_util::join!(lookup(&chain_state, &[ADDRESS, ADDRESS]), async {
+                sender.send_replace(Some(Validation::valid(ADDRESS, token_data)));
+            },);
+        assert_eq!(validations.len(), 2);
+        assert!(validations.iter().all(is_valid));
+    }
+
+    #[tokio::test]
+    async fn validates_addresses_whose_pending_lookup_was_cancelled() {
+        let chain_state = unreachable_chain_state();
+        let (sender, receiver) = watch::channel(None);
+        chain_state
+            .pending_validations
+            .pending
+            .lock()
+            .unwrap()
+            .insert(ADDRESS, receiver);
+        drop(sender);
+
+        let validations = lookup(&chain_state, &[ADDRESS]).await;
+        assert_eq!(validations.len(), 1);
+        assert!(matches!(
+            validations[0].status,
+            ValidationStatus::Invalid {
+                reason: InvalidReason::LookupFailed,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_duplicates_once_per_occurrence() {
+        let chain_state = unreachable_chain_state();
+        let other = address!("60e4d786628fea6478f785a6d7e704777c86a7c6");
+
+        let validations = lookup(&chain_state, &[ADDRESS, other, ADDRESS]).await;
+        let addresses = validations
+            .iter()
+            .map(|validation| validation.address)
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, [ADDRESS, other, ADDRESS]);
+        assert!(chain_state
+            .pending_validations
+            .pending
+            .lock()
+            .unwrap()
+            .is_empty());
+    }
+}
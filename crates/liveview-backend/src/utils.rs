@@ -1,26 +1,127 @@
-use serde::Serialize;
-use url::Url;
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize)]
+/// Base64 as found in on-chain data URIs, where padding is often omitted
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub(crate) enum MetadataType {
     Url,
     Data,
 }
 
-pub(crate) fn extract_metadata_url(url: Url) -> Option<(String, MetadataType)> {
-    let scheme = url.scheme();
-
-    if scheme == "http" || scheme == "https" {
-        Some((url.to_string(), MetadataType::Url))
-    } else if scheme == "ipfs" {
-        Some((
-            format!("https://ipfs.io/ipfs/{}{}", url.domain()?, url.path()),
-            MetadataType::Url,
-        ))
-    } else if scheme == "data" {
-        /* JWT data */
-        Some((url.to_string(), MetadataType::Data))
+/// Decoded content of a `data:` URI
+pub(crate) struct DataUri {
+    pub(crate) media_type: String,
+    pub(crate) data: Vec<u8>,
+}
+
+/// Whether `uri` is a `data:` URI
+pub(crate) fn is_data_uri(uri: &str) -> bool {
+    uri.get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+}
+
+/// Media type of a `data:` URI without decoding its data, defaulting to `text/plain`
+pub(crate) fn data_uri_media_type(uri: &str) -> Option<String> {
+    let (header, _) = uri.get(5..)?.split_once(',')?;
+    let media_type = header.split(';').next().unwrap_or_default().trim();
+
+    if media_type.is_empty() {
+        Some("text/plain".to_owned())
     } else {
-        None
+        Some(media_type.to_ascii_lowercase())
+    }
+}
+
+/// Decode a base64 or percent-encoded `data:` URI
+pub(crate) fn decode_data_uri(uri: &str) -> Option<DataUri> {
+    if !is_data_uri(uri) {
+        return None;
+    }
+
+    let media_type = data_uri_media_type(uri)?;
+    let (header, data) = uri[5..].split_once(',')?;
+    let is_base64 = header
+        .split(';')
+        .skip(1)
+        .any(|param| param.trim().eq_ignore_ascii_case("base64"));
+
+    let data = percent_encoding::percent_decode_str(data).collect::<Vec<u8>>();
+    let data = if is_base64 {
+        let data = data
+            .into_iter()
+            .filter(|byte| !byte.is_ascii_whitespace())
+            .collect::<Vec<u8>>();
+        BASE64.decode(data).ok()?
+    } else {
+        data
+    };
+
+    Some(DataUri { media_type, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_data_uris() {
+        for (uri, media_type, data) in [
+            (
+                "data:application/json;base64,eyJuYW1lIjoiQSJ9",
+                "application/json",
+                r#"{"name":"A"}"#,
+            ),
+            // Padding is often left out, and whitespace slips into long literals
+            ("data:text/plain;base64,YWI", "text/plain", "ab"),
+            ("data:text/plain;base64,YW Jj\nZA==", "text/plain", "abcd"),
+            (
+                "data:application/json;charset=utf-8;BASE64,eyJuYW1lIjoiQSJ9",
+                "application/json",
+                r#"{"name":"A"}"#,
+            ),
+            (
+                r#"data:application/json;utf8,{"name":"A"}"#,
+                "application/json",
+                r#"{"name":"A"}"#,
+            ),
+            (
+                "data:application/json,%7B%22name%22%3A%22A%20B%22%7D",
+                "application/json",
+                r#"{"name":"A B"}"#,
+            ),
+            // A `%` which doesn't start an escape is kept
+            (
+                r#"data:application/json;utf8,{"name":"100%"}"#,
+                "application/json",
+                r#"{"name":"100%"}"#,
+            ),
+            ("DATA:Image/SVG+XML;utf8,<svg/>", "image/svg+xml", "<svg/>"),
+            ("data:,hello", "text/plain", "hello"),
+        ] {
+            let decoded = decode_data_uri(uri).unwrap_or_else(|| panic!("{uri} is not decoded"));
+            assert_eq!(decoded.media_type, media_type, "{uri}");
+            assert_eq!(decoded.data, data.as_bytes(), "{uri}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_data_uris() {
+        for uri in [
+            "https://example.com/1.json",
+            "data:application/json;base64",
+            "data:application/json;base64,!!!",
+            "data:",
+        ] {
+            assert!(decode_data_uri(uri).is_none(), "{uri}");
+        }
     }
 }